@@ -0,0 +1,34 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use rand::SeedableRng;
+
+use lib::config::SimulationConfig;
+use lib::simulation::colony::Colony;
+use lib::simulation::utils::SimulationRng;
+
+
+// Time a single tick of Colony::update, which runs the whole colony through the decision
+// network as one batch, for colonies of increasing size
+fn colony_update(c: &mut Criterion) {
+    let config = SimulationConfig::new();
+    let mut group = c.benchmark_group("colony_update");
+    group.sample_size(20);
+    for num_ants in [20, 500, 10_000].iter() {
+        let mut rng = SimulationRng::seed_from_u64(0);
+        let mut environment = config.environment.build().unwrap();
+        let nest_location = environment.nest_location().unwrap();
+        let mut colony = Colony::at_location(*num_ants, config.build_network(config.seed), nest_location, &config.ant, &mut rng);
+        // Spread the ants out first so the benchmark isn't just ants piled up on the nest
+        for _ in 0..50 {
+            colony.update(&mut environment, &mut rng);
+        }
+
+        group.bench_with_input(BenchmarkId::from_parameter(num_ants), num_ants, |b, _| {
+            b.iter(|| colony.update(&mut environment, &mut rng))
+        });
+    }
+    group.finish();
+}
+
+
+criterion_group!(benches, colony_update);
+criterion_main!(benches);
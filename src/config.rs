@@ -0,0 +1,585 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use ndarray::{Array, Dim};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::neural_network::mlp::{MLP, ModelMetadata, default_activations};
+use crate::neural_network::utils::Activation;
+use crate::optimization::{EvolutionStrategy, OptimizerKind};
+use crate::optimization::cma_es::CmaEs;
+use crate::optimization::openai_es::OpenAiEs;
+use crate::optimization::snes::SeparableNes;
+use crate::simulation::colony::{AntSettings, FEATURE_SCHEMA_VERSION};
+use crate::simulation::environment::{DiffusionSettings, Environment};
+use crate::simulation::map::{load_map, MapError};
+use crate::simulation::utils::SimulationRng;
+use crate::simulation::simulation::SimulationResult;
+use crate::training::reward::{Reward, RewardTerm, WeightedTerm};
+
+
+// Everything needed to set up and score a simulation, shared by both binaries. Every section
+// and field is optional in the file, anything left out takes the value from new().
+
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String),
+    Map(MapError),
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvironmentConfig {
+    pub arena_size: usize,
+    // A text or PNG map to load instead of the default empty arena, see simulation::map
+    pub map: Option<String>,
+    pub diffusion: DiffusionSettings,
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColonyConfig {
+    pub num_ants: usize,
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    // The input size is set by the ant's feature vector, so only the layer sizes are given here.
+    // The last entry is the output size.
+    pub hidden_sizes: Vec<usize>,
+    // One per entry of hidden_sizes, when left out every hidden layer uses Relu and the output
+    // is linear
+    pub activations: Option<Vec<Activation>>,
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "RewardConfigFile")]
+pub struct RewardConfig {
+    // The reward is the weighted sum of these terms, see RewardTerm for what each one measures
+    pub terms: Vec<WeightedTerm>,
+}
+
+
+// What a [reward] section may contain. Configs and checkpoints written before reward terms
+// existed give the three weights below instead of terms, and still load as those three terms.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RewardConfigFile {
+    terms: Option<Vec<WeightedTerm>>,
+    food_returned_weight: Option<f64>,
+    proportion_explored_weight: Option<f64>,
+    food_remaining_weight: Option<f64>,
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrainingConfig {
+    pub optimizer: OptimizerKind,
+    pub num_generations: usize,
+    pub population_size: usize,
+    pub runs_per_evaluation: usize,
+    // Threads used to run evaluation simulations, 0 uses one per CPU core
+    pub num_workers: usize,
+    // learning_rate_mu is used by OpenAiEs and SeparableNes, learning_rate_sigma only by
+    // SeparableNes. CMA-ES sets its own rates from the number of parameters.
+    pub learning_rate_mu: f32,
+    pub learning_rate_sigma: f32,
+    pub initial_sigma: f32,
+    // Where the trained model is written, as a self-describing model file or as bare weights
+    // if the path ends in .npy
+    pub output_path: String,
+    // A checkpoint is written every checkpoint_interval generations (0 turns them off) and
+    // once more when training finishes
+    pub checkpoint_path: String,
+    pub checkpoint_interval: usize,
+    // Per generation statistics, as CSV if the path ends in .csv and JSON lines otherwise. An
+    // empty path turns the log off.
+    pub log_path: String,
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationConfig {
+    pub seed: u64,
+    pub num_steps: usize,
+    pub environment: EnvironmentConfig,
+    pub colony: ColonyConfig,
+    pub ant: AntSettings,
+    pub network: NetworkConfig,
+    pub reward: RewardConfig,
+    pub training: TrainingConfig,
+}
+
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not read config file: {}", e),
+            ConfigError::Parse(e) => write!(f, "could not parse config file: {}", e),
+            ConfigError::Invalid(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Map(e) => write!(f, "invalid config: {}", e),
+        }
+    }
+}
+
+
+impl std::error::Error for ConfigError {}
+
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+
+impl From<MapError> for ConfigError {
+    fn from(e: MapError) -> ConfigError {
+        ConfigError::Map(e)
+    }
+}
+
+
+impl EnvironmentConfig {
+    pub fn new() -> EnvironmentConfig {
+        EnvironmentConfig {
+            arena_size: 75,
+            map: None,
+            diffusion: DiffusionSettings::new(),
+        }
+    }
+
+    pub fn build(&self) -> Result<Environment, MapError> {
+        match &self.map {
+            Some(path) => load_map(path, self.diffusion),
+            None => Ok(Environment::with_diffusion_settings(self.arena_size, self.diffusion)),
+        }
+    }
+}
+
+
+impl ColonyConfig {
+    pub fn new() -> ColonyConfig {
+        ColonyConfig {
+            num_ants: 20,
+        }
+    }
+}
+
+
+impl NetworkConfig {
+    pub fn new() -> NetworkConfig {
+        NetworkConfig {
+            hidden_sizes: vec![16, 2],
+            activations: None,
+        }
+    }
+
+    pub fn activations(&self) -> Vec<Activation> {
+        match &self.activations {
+            Some(activations) => activations.clone(),
+            None => default_activations(self.hidden_sizes.len()),
+        }
+    }
+}
+
+
+impl RewardConfig {
+    pub fn new() -> RewardConfig {
+        RewardConfig {
+            terms: vec![
+                WeightedTerm::new(RewardTerm::FoodReturned, 1.0),
+                WeightedTerm::new(RewardTerm::ProportionExplored, 1.0),
+                WeightedTerm::new(RewardTerm::FoodRemaining, -0.1),
+            ],
+        }
+    }
+}
+
+
+impl TryFrom<RewardConfigFile> for RewardConfig {
+    type Error = String;
+
+    fn try_from(file: RewardConfigFile) -> Result<RewardConfig, String> {
+        let legacy_weights = [file.food_returned_weight, file.proportion_explored_weight, file.food_remaining_weight];
+        if legacy_weights.iter().all(Option::is_none) {
+            return Ok(match file.terms {
+                Some(terms) => RewardConfig { terms },
+                None => RewardConfig::new(),
+            })
+        }
+        if file.terms.is_some() {
+            return Err("give either terms or the older food_returned_weight, proportion_explored_weight \
+                        and food_remaining_weight, not both".to_string())
+        }
+
+        // Weights left out took the same defaults as they do now
+        Ok(RewardConfig {
+            terms: vec![
+                WeightedTerm::new(RewardTerm::FoodReturned, file.food_returned_weight.unwrap_or(1.0)),
+                WeightedTerm::new(RewardTerm::ProportionExplored, file.proportion_explored_weight.unwrap_or(1.0)),
+                WeightedTerm::new(RewardTerm::FoodRemaining, file.food_remaining_weight.unwrap_or(-0.1)),
+            ],
+        })
+    }
+}
+
+
+impl Reward for RewardConfig {
+    fn reward(&self, simulation_result: &SimulationResult) -> f64 {
+        self.terms.reward(simulation_result)
+    }
+}
+
+
+impl TrainingConfig {
+    pub fn new() -> TrainingConfig {
+        TrainingConfig {
+            optimizer: OptimizerKind::SeparableNes,
+            num_generations: 500,
+            population_size: 64,
+            runs_per_evaluation: 10,
+            num_workers: 0,
+            learning_rate_mu: 0.01,
+            learning_rate_sigma: 0.001,
+            initial_sigma: 0.1,
+            output_path: "trial_4.mlp".to_string(),
+            checkpoint_path: "checkpoint.json".to_string(),
+            checkpoint_interval: 10,
+            log_path: "training_log.csv".to_string(),
+        }
+    }
+}
+
+
+impl SimulationConfig {
+    pub fn new() -> SimulationConfig {
+        SimulationConfig {
+            seed: 0,
+            num_steps: 500,
+            environment: EnvironmentConfig::new(),
+            colony: ColonyConfig::new(),
+            ant: AntSettings::new(),
+            network: NetworkConfig::new(),
+            reward: RewardConfig::new(),
+            training: TrainingConfig::new(),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<SimulationConfig, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let is_json = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => extension.eq_ignore_ascii_case("json"),
+            None => false,
+        };
+        let config: SimulationConfig = if is_json {
+            serde_json::from_str(&text).map_err(|e| ConfigError::Parse(e.to_string()))?
+        } else {
+            toml::from_str(&text).map_err(|e| ConfigError::Parse(e.to_string()))?
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |section: &str, message: String| Err(ConfigError::Invalid(format!("[{}] {}", section, message)));
+
+        if self.num_steps == 0 {
+            return Err(ConfigError::Invalid("num_steps must be at least 1".to_string()))
+        }
+        // The default arena has a 5x5 nest in one corner and 5x5 food patch in the other
+        if self.environment.map.is_none() && self.environment.arena_size < 12 {
+            return invalid("environment", format!("arena_size must be at least 12, got {}", self.environment.arena_size))
+        }
+        if let Err(message) = self.environment.diffusion.validate() {
+            return invalid("environment.diffusion", message)
+        }
+        if self.colony.num_ants == 0 {
+            return invalid("colony", "num_ants must be at least 1".to_string())
+        }
+        if let Err(message) = self.ant.validate() {
+            return invalid("ant", message)
+        }
+        if self.network.hidden_sizes.is_empty() || self.network.hidden_sizes.contains(&0) {
+            return invalid("network", format!("hidden_sizes must be non-empty with no zero sizes, got {:?}", self.network.hidden_sizes))
+        }
+        if let Some(activations) = &self.network.activations {
+            if activations.len() != self.network.hidden_sizes.len() {
+                return invalid("network", format!(
+                    "activations needs one entry per layer, got {} for hidden_sizes {:?}",
+                    activations.len(), self.network.hidden_sizes
+                ))
+            }
+        }
+        if self.reward.terms.is_empty() {
+            return invalid("reward", "terms needs at least one entry".to_string())
+        }
+        for weighted_term in self.reward.terms.iter() {
+            if !weighted_term.weight.is_finite() {
+                return invalid("reward", format!("weight of {:?} must be finite", weighted_term.term))
+            }
+        }
+        if self.training.population_size < 2 {
+            return invalid("training", "population_size must be at least 2".to_string())
+        }
+        if self.training.runs_per_evaluation == 0 {
+            return invalid("training", "runs_per_evaluation must be at least 1".to_string())
+        }
+        if self.environment.map.is_some() {
+            self.environment.build()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn input_dimension(&self) -> usize {
+        self.ant.feature_dimension()
+    }
+
+    pub fn num_parameters(&self) -> usize {
+        MLP::num_parameters(self.input_dimension(), &self.network.hidden_sizes)
+    }
+
+    // A randomly initialised network of the config's shape, the same one every time for a seed
+    pub fn build_network(&self, seed: u64) -> MLP {
+        let mut rng = SimulationRng::seed_from_u64(seed);
+
+        MLP::new_using(self.input_dimension(), self.network.hidden_sizes.clone(), self.network.activations(), &mut rng)
+    }
+
+    pub fn build_optimizer(&self, initial_mean: Array<f32, Dim<[usize; 1]>>) -> EvolutionStrategy {
+        let training = &self.training;
+        match training.optimizer {
+            OptimizerKind::OpenAiEs => EvolutionStrategy::OpenAiEs(OpenAiEs::new(
+                initial_mean, training.initial_sigma, training.learning_rate_mu, training.population_size, self.seed
+            )),
+            OptimizerKind::SeparableNes => EvolutionStrategy::SeparableNes(SeparableNes::new(
+                initial_mean,
+                training.initial_sigma,
+                training.learning_rate_mu,
+                training.learning_rate_sigma,
+                training.population_size,
+                self.seed
+            )),
+            OptimizerKind::CmaEs => EvolutionStrategy::CmaEs(Box::new(CmaEs::new(
+                initial_mean, training.initial_sigma, training.population_size, self.seed
+            ))),
+        }
+    }
+
+    pub fn check_network(&self, decision_network: &MLP, metadata: &ModelMetadata) -> Result<(), ConfigError> {
+        // Makes sure a loaded model can actually be driven by ants built from this config
+        if metadata.feature_schema_version != FEATURE_SCHEMA_VERSION {
+            return Err(ConfigError::Invalid(format!(
+                "model was trained on feature schema version {} but this build uses version {}",
+                metadata.feature_schema_version, FEATURE_SCHEMA_VERSION
+            )))
+        }
+        if decision_network.input_dimension() != self.input_dimension() {
+            return Err(ConfigError::Invalid(format!(
+                "model takes {} inputs but ants with num_samples = {} produce {}",
+                decision_network.input_dimension(), self.ant.num_samples, self.input_dimension()
+            )))
+        }
+
+        Ok(())
+    }
+
+    pub fn model_metadata(&self) -> ModelMetadata {
+        let mut metadata = ModelMetadata { feature_schema_version: FEATURE_SCHEMA_VERSION, ..ModelMetadata::default() };
+        metadata.training.insert("config".to_string(), serde_json::to_string(self).unwrap());
+
+        metadata
+    }
+}
+
+
+impl Default for EnvironmentConfig {
+    fn default() -> EnvironmentConfig {
+        EnvironmentConfig::new()
+    }
+}
+
+
+impl Default for ColonyConfig {
+    fn default() -> ColonyConfig {
+        ColonyConfig::new()
+    }
+}
+
+
+impl Default for NetworkConfig {
+    fn default() -> NetworkConfig {
+        NetworkConfig::new()
+    }
+}
+
+
+impl Default for RewardConfig {
+    fn default() -> RewardConfig {
+        RewardConfig::new()
+    }
+}
+
+
+impl Default for TrainingConfig {
+    fn default() -> TrainingConfig {
+        TrainingConfig::new()
+    }
+}
+
+
+impl Default for SimulationConfig {
+    fn default() -> SimulationConfig {
+        SimulationConfig::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimization::Optimizer;
+    #[test]
+    fn test_default_config_is_valid() {
+        let config = SimulationConfig::new();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.input_dimension(), 38);
+        assert_eq!(config.num_parameters(), config.build_network(config.seed).flatten_weights().len());
+        assert_eq!(config.build_network(3).flatten_weights(), config.build_network(3).flatten_weights());
+        assert_ne!(config.build_network(3).flatten_weights(), config.build_network(4).flatten_weights());
+    }
+
+    #[test]
+    fn test_check_network() {
+        let config = SimulationConfig::new();
+        let metadata = config.model_metadata();
+        assert!(config.check_network(&config.build_network(config.seed), &metadata).is_ok());
+
+        let mut other_config = SimulationConfig::new();
+        other_config.ant.num_samples = 3;
+        assert!(config.check_network(&other_config.build_network(other_config.seed), &metadata).is_err());
+
+        let mut old_metadata = metadata.clone();
+        old_metadata.feature_schema_version = FEATURE_SCHEMA_VERSION + 1;
+        assert!(config.check_network(&config.build_network(config.seed), &old_metadata).is_err());
+    }
+
+    #[test]
+    fn test_build_optimizer() {
+        let mut config = SimulationConfig::new();
+        config.training.population_size = 6;
+        for kind in [OptimizerKind::OpenAiEs, OptimizerKind::SeparableNes, OptimizerKind::CmaEs].iter() {
+            config.training.optimizer = *kind;
+            let mut optimizer = config.build_optimizer(Array::zeros(config.num_parameters()));
+            let population = optimizer.ask();
+
+            assert_eq!(optimizer.kind(), *kind);
+            assert_eq!(population.len(), 6);
+            assert!(population.iter().all(|x| x.len() == config.num_parameters()));
+        }
+    }
+
+    #[test]
+    fn test_load_example_configs() {
+        let toml_config = SimulationConfig::from_file("configs/default.toml").unwrap();
+        let json_config = SimulationConfig::from_file("configs/maze.json").unwrap();
+
+        assert_eq!(toml_config.environment.arena_size, SimulationConfig::new().environment.arena_size);
+        assert_eq!(json_config.environment.map, Some("maps/maze.txt".to_string()));
+    }
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let config: SimulationConfig = toml::from_str("num_steps = 100\n[colony]\nnum_ants = 5\n").unwrap();
+
+        assert_eq!(config.num_steps, 100);
+        assert_eq!(config.colony.num_ants, 5);
+        assert_eq!(config.ant.num_samples, AntSettings::new().num_samples);
+        assert_eq!(config.network.activations(), vec![Activation::Relu, Activation::Identity]);
+
+        let config: SimulationConfig = toml::from_str("[network]\nactivations = [\"Elu\", \"Tanh\"]\n").unwrap();
+        assert_eq!(config.build_network(config.seed).activations(), vec![Activation::Elu, Activation::Tanh]);
+        let config: SimulationConfig = toml::from_str("[[reward.terms]]\nterm = \"DeliveryRate\"\nweight = 2.0\n").unwrap();
+        assert_eq!(config.reward.terms, vec![WeightedTerm::new(RewardTerm::DeliveryRate, 2.0)]);
+    }
+
+    #[test]
+    fn test_legacy_reward_weights() {
+        let config: SimulationConfig = toml::from_str("[reward]\nfood_returned_weight = 2.0\nfood_remaining_weight = -0.5\n").unwrap();
+        assert_eq!(config.reward.terms, vec![
+            WeightedTerm::new(RewardTerm::FoodReturned, 2.0),
+            WeightedTerm::new(RewardTerm::ProportionExplored, 1.0),
+            WeightedTerm::new(RewardTerm::FoodRemaining, -0.5),
+        ]);
+        let config: SimulationConfig = serde_json::from_str(r#"{"reward": {}}"#).unwrap();
+        assert_eq!(config.reward.terms, RewardConfig::new().terms);
+
+        // Saved configs use terms, so they load back the same
+        let text = serde_json::to_string(&config).unwrap();
+        let reloaded: SimulationConfig = serde_json::from_str(&text).unwrap();
+        assert_eq!(reloaded.reward.terms, config.reward.terms);
+
+        let both = "[reward]\nfood_returned_weight = 2.0\n[[reward.terms]]\nterm = \"DeliveryRate\"\nweight = 2.0\n";
+        assert!(toml::from_str::<SimulationConfig>(both).is_err());
+        assert!(toml::from_str::<SimulationConfig>("[reward]\nfood_weight = 2.0\n").is_err());
+    }
+
+    #[test]
+    fn test_invalid_configs() {
+        let mut config = SimulationConfig::new();
+        config.ant.velocity = 1.5;
+        match config.validate() {
+            Err(ConfigError::Invalid(message)) => assert!(message.contains("velocity")),
+            _ => panic!("expected an invalid velocity"),
+        }
+
+        let mut config = SimulationConfig::new();
+        config.environment.diffusion.food_pheromone.evaporation_rate = -0.1;
+        match config.validate() {
+            Err(ConfigError::Invalid(message)) => assert!(message.contains("food_pheromone.evaporation_rate")),
+            _ => panic!("expected an invalid evaporation rate"),
+        }
+
+        let mut config = SimulationConfig::new();
+        config.network.activations = Some(vec![Activation::Tanh]);
+        match config.validate() {
+            Err(ConfigError::Invalid(message)) => assert!(message.contains("activations")),
+            _ => panic!("expected too few activations"),
+        }
+
+        let mut config = SimulationConfig::new();
+        config.reward.terms.push(WeightedTerm::new(RewardTerm::EnergySpent, f64::NAN));
+        match config.validate() {
+            Err(ConfigError::Invalid(message)) => assert!(message.contains("EnergySpent")),
+            _ => panic!("expected a non-finite reward weight"),
+        }
+
+        let mut config = SimulationConfig::new();
+        config.environment.map = Some("maps/does_not_exist.txt".to_string());
+        match config.validate() {
+            Err(ConfigError::Map(_)) => {},
+            _ => panic!("expected a missing map"),
+        }
+
+        match toml::from_str::<SimulationConfig>("[colony]\nnum_ant = 5\n") {
+            Err(e) => assert!(e.to_string().contains("num_ant")),
+            Ok(_) => panic!("expected an unknown field error"),
+        }
+    }
+}
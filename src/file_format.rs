@@ -0,0 +1,148 @@
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+
+// Model, replay and snapshot files share a layout: a magic line saying what the file is, a single
+// line of JSON describing it, so `head -2` shows what's inside, and then the body. Every header
+// has a format_version field, which is checked before the rest of the header is parsed.
+
+
+#[derive(Debug)]
+pub enum FileError {
+    Io(std::io::Error),
+    Format(String),
+}
+
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::Io(e) => write!(f, "could not access file: {}", e),
+            FileError::Format(e) => write!(f, "invalid file: {}", e),
+        }
+    }
+}
+
+
+impl std::error::Error for FileError {}
+
+
+impl From<std::io::Error> for FileError {
+    fn from(e: std::io::Error) -> FileError {
+        FileError::Io(e)
+    }
+}
+
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    format_version: u32,
+}
+
+
+pub struct FileFormat {
+    // What the file holds, for error messages
+    pub name: &'static str,
+    pub magic: &'static [u8],
+    pub version: u32,
+}
+
+
+impl FileFormat {
+    pub fn write<P: AsRef<Path>, H: Serialize>(&self, path: P, header: &H, body: &[u8]) -> Result<(), FileError> {
+        let mut bytes: Vec<u8> = self.magic.to_vec();
+        bytes.extend(serde_json::to_string(header).unwrap().into_bytes());
+        bytes.push(b'\n');
+        bytes.extend(body);
+        fs::write(path, bytes)?;
+
+        Ok(())
+    }
+
+    // Returns the header and the rest of the file
+    pub fn read<P: AsRef<Path>, H: DeserializeOwned>(&self, path: P) -> Result<(H, Vec<u8>), FileError> {
+        let mut bytes = fs::read(path)?;
+        if !bytes.starts_with(self.magic) {
+            return Err(FileError::Format(format!("missing {} file header", self.name)))
+        }
+        let header_end = match bytes[self.magic.len()..].iter().position(|byte| *byte == b'\n') {
+            Some(position) => self.magic.len() + position,
+            None => return Err(FileError::Format("unterminated header".to_string())),
+        };
+        let header_bytes = &bytes[self.magic.len()..header_end];
+        let version: VersionOnly = serde_json::from_slice(header_bytes)
+            .map_err(|e| FileError::Format(format!("could not parse header: {}", e)))?;
+        if version.format_version != self.version {
+            return Err(FileError::Format(format!(
+                "unsupported {} format version {}", self.name, version.format_version
+            )))
+        }
+        let header: H = serde_json::from_slice(header_bytes)
+            .map_err(|e| FileError::Format(format!("could not parse header: {}", e)))?;
+        let body = bytes.split_off(header_end + 1);
+
+        Ok((header, body))
+    }
+}
+
+
+// Bodies too large to leave as JSON are bincode encoded and zlib compressed
+pub fn encode_body<T: Serialize>(value: &T) -> Result<Vec<u8>, FileError> {
+    let body = bincode::serialize(value).map_err(|e| FileError::Format(format!("could not encode body: {}", e)))?;
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&body)?;
+
+    Ok(encoder.finish()?)
+}
+
+
+pub fn decode_body<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, FileError> {
+    let mut body = Vec::new();
+    ZlibDecoder::new(bytes).read_to_end(&mut body)?;
+
+    bincode::deserialize(&body).map_err(|e| FileError::Format(format!("could not decode body: {}", e)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestHeader {
+        format_version: u32,
+        name: String,
+    }
+
+    const TEST_FORMAT: FileFormat = FileFormat { name: "test", magic: b"ANTTEST\n", version: 2 };
+
+    #[test]
+    fn test_write_and_read() {
+        let path = std::env::temp_dir().join("ant_sim_test_file_format.test");
+        let header = TestHeader { format_version: 2, name: "first".to_string() };
+        let body = encode_body(&vec![1.5f64, -2.0]).unwrap();
+        TEST_FORMAT.write(&path, &header, &body).unwrap();
+        let (read_header, read_body): (TestHeader, Vec<u8>) = TEST_FORMAT.read(&path).unwrap();
+        assert_eq!(read_header, header);
+        assert_eq!(decode_body::<Vec<f64>>(&read_body).unwrap(), vec![1.5, -2.0]);
+
+        // The version is checked before the header is parsed, so later formats can change it
+        std::fs::write(&path, b"ANTTEST\n{\"format_version\": 3}\n").unwrap();
+        match TEST_FORMAT.read::<_, TestHeader>(&path) {
+            Err(FileError::Format(message)) => assert!(message.contains("version 3")),
+            _ => panic!("expected an unsupported version"),
+        }
+        for contents in [&b"ANTMLP\n{}\n"[..], b"ANTTEST\n{\"format_version\": 2}", b"ANTTEST\nnot json\n"].iter() {
+            std::fs::write(&path, contents).unwrap();
+            assert!(matches!(TEST_FORMAT.read::<_, TestHeader>(&path), Err(FileError::Format(_))));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
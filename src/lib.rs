@@ -1,2 +1,8 @@
+pub mod config;
+pub mod file_format;
 pub mod neural_network;
-pub mod simulation;
\ No newline at end of file
+pub mod optimization;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod simulation;
+pub mod training;
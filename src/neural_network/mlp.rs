@@ -1,24 +1,42 @@
 extern crate openblas_src;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::path::Path;
+
 use ndarray::{Array, Dim, s};
 use ndarray_rand::RandomExt;
 use ndarray_rand::rand_distr::Uniform;
 use ndarray_npy::{read_npy, write_npy};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::file_format::{FileError, FileFormat};
+use crate::neural_network::utils::{Activation, reshape_array};
+
 
-use crate::neural_network::utils::{relu, reshape_array};
+// Model files have a ModelHeader, and the flattened weights (see MLP::flatten_weights) as little
+// endian f32s for their body
+const MODEL_FILE: FileFormat = FileFormat {
+    name: "model",
+    magic: b"ANTMLP\n",
+    version: 1,
+};
 
 
+#[derive(Clone, Serialize, Deserialize)]
 struct Layer {
     w: Array<f32, Dim<[usize; 2]>>,
     b: Array<f32, Dim<[usize; 2]>>,
-    activation: bool,
+    activation: Activation,
 }
 
 
 impl Layer {
-    fn new(num_input: usize, num_output: usize, activation: bool) -> Layer {
+    fn new<R: Rng>(num_input: usize, num_output: usize, activation: Activation, rng: &mut R) -> Layer {
         let distribution = Uniform::new(-0.5, 0.5);
-        let w = Array::random((num_input, num_output), distribution);
-        let b = Array::random((1, num_output), distribution);
+        let w = Array::random_using((num_input, num_output), distribution, rng);
+        let b = Array::random_using((1, num_output), distribution, rng);
 
         Layer {
             w: w,
@@ -27,7 +45,7 @@ impl Layer {
         }
     }
 
-    fn from_existing_weights(weights: Array<f32, Dim<[usize; 2]>>, bias: Array<f32, Dim<[usize; 2]>>, activation: bool) -> Layer {
+    fn from_existing_weights(weights: Array<f32, Dim<[usize; 2]>>, bias: Array<f32, Dim<[usize; 2]>>, activation: Activation) -> Layer {
         Layer {
             w: weights, 
             b: bias,
@@ -38,34 +56,124 @@ impl Layer {
     fn forward(&self, input: Array<f32, Dim<[usize; 2]>>) -> Array<f32, Dim<[usize; 2]>> {
         let h = input.dot(&self.w) + &self.b;
 
-        if self.activation {
-            return relu(h)
-        }
-
-        h
-        
+        self.activation.apply(h)
     }
 }
 
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct MLP {
     layers: Vec<Layer>,
 }
 
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerHeader {
+    pub num_input: usize,
+    pub num_output: usize,
+    pub activation: Activation,
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelMetadata {
+    // Version of the feature vector the network was trained on, so a model can't silently be
+    // fed inputs laid out differently from the ones it learned
+    pub feature_schema_version: u32,
+    // Free form training information, e.g. the config and number of generations
+    pub training: BTreeMap<String, String>,
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ModelHeader {
+    format_version: u32,
+    layers: Vec<LayerHeader>,
+    metadata: ModelMetadata,
+}
+
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(std::io::Error),
+    Format(String),
+    ShapeMismatch { expected_parameters: usize, found_parameters: usize },
+}
+
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Io(e) => write!(f, "could not access model file: {}", e),
+            ModelError::Format(e) => write!(f, "invalid model file: {}", e),
+            ModelError::ShapeMismatch { expected_parameters, found_parameters } => write!(
+                f, "model file layers need {} parameters but the file contains {}",
+                expected_parameters, found_parameters
+            ),
+        }
+    }
+}
+
+
+impl std::error::Error for ModelError {}
+
+
+impl From<std::io::Error> for ModelError {
+    fn from(e: std::io::Error) -> ModelError {
+        ModelError::Io(e)
+    }
+}
+
+
+impl From<FileError> for ModelError {
+    fn from(e: FileError) -> ModelError {
+        match e {
+            FileError::Io(e) => ModelError::Io(e),
+            FileError::Format(e) => ModelError::Format(e),
+        }
+    }
+}
+
+
+// ReLU on every hidden layer and a linear output, what networks used before activations could
+// be chosen
+pub fn default_activations(num_layers: usize) -> Vec<Activation> {
+    let mut activations = vec![Activation::Relu; num_layers];
+    if let Some(output_activation) = activations.last_mut() {
+        *output_activation = Activation::Identity;
+    }
+
+    activations
+}
+
+
+// The other way round from default_activations: linear hidden layers and a ReLU on the output.
+// from_flattened_weights used to apply this to weights loaded from bare .npy files, so networks
+// that were trained and saved that way only behave as they did with these activations.
+pub fn legacy_activations(num_layers: usize) -> Vec<Activation> {
+    let mut activations = vec![Activation::Identity; num_layers];
+    if let Some(output_activation) = activations.last_mut() {
+        *output_activation = Activation::Relu;
+    }
+
+    activations
+}
+
+
 impl MLP {
-    pub fn new(input_dimension: usize, hidden_sizes: Vec<usize>) -> MLP {
-        // NOTE: The last element of the hidden_sizes vector is the output size
+    // Initialised from the thread's RNG, use new_using for a reproducible network
+    pub fn new(input_dimension: usize, hidden_sizes: Vec<usize>, activations: Vec<Activation>) -> MLP {
+        MLP::new_using(input_dimension, hidden_sizes, activations, &mut rand::thread_rng())
+    }
+
+    pub fn new_using<R: Rng>(input_dimension: usize, hidden_sizes: Vec<usize>, activations: Vec<Activation>, rng: &mut R) -> MLP {
+        // NOTE: The last element of the hidden_sizes vector is the output size, activations has
+        // one entry per element of hidden_sizes
+        assert_eq!(hidden_sizes.len(), activations.len(), "need one activation per layer");
         let mut layers: Vec<Layer> = Vec::new();
         let mut num_input = input_dimension;
-        let mut activation: bool;
         for i in 0..hidden_sizes.len() {
-            if i == hidden_sizes.len() - 1 {
-                activation = false;
-            } else {
-                activation = true
-            }
-            let layer = Layer::new(num_input, hidden_sizes[i], activation);
+            let layer = Layer::new(num_input, hidden_sizes[i], activations[i], rng);
             layers.push(layer);
             num_input = hidden_sizes[i];
         }
@@ -73,20 +181,27 @@ impl MLP {
         MLP { layers }
     }
 
+    pub fn num_parameters(input_dimension: usize, hidden_sizes: &[usize]) -> usize {
+        let mut num_parameters = 0;
+        let mut num_input = input_dimension;
+        for hidden_size in hidden_sizes.iter() {
+            num_parameters += num_input * hidden_size + hidden_size;
+            num_input = *hidden_size;
+        }
+
+        num_parameters
+    }
+
     pub fn from_flattened_weights(
         input_dimension: usize, 
         hidden_sizes: Vec<usize>, 
+        activations: Vec<Activation>,
         weight_array: Array<f32, Dim<[usize; 1]>>) -> MLP {
+            assert_eq!(hidden_sizes.len(), activations.len(), "need one activation per layer");
             let mut layers: Vec<Layer> = Vec::new();
             let mut num_input = input_dimension;
             let mut current_array_index: usize = 0;
-            let mut activation: bool;
             for i in 0..hidden_sizes.len() {
-                if i == hidden_sizes.len() - 1 {
-                    activation = true;
-                } else {
-                    activation = false;
-                }
                 let w_end = current_array_index + num_input * hidden_sizes[i];
 
                 let array_slice = weight_array.slice(s![current_array_index..w_end]);
@@ -100,16 +215,95 @@ impl MLP {
 
                 num_input = hidden_sizes[i];
 
-                layers.push(Layer::from_existing_weights(w, b, activation));
+                layers.push(Layer::from_existing_weights(w, b, activations[i]));
             }
 
             MLP { layers }
         }
 
-    pub fn from_file(input_dimension: usize, hidden_sizes: Vec<usize>, path: String) -> MLP {
+    pub fn from_file(input_dimension: usize, hidden_sizes: Vec<usize>, activations: Vec<Activation>, path: String) -> MLP {
         let weight_array = read_npy(path).unwrap();
 
-        MLP::from_flattened_weights(input_dimension, hidden_sizes, weight_array)
+        MLP::from_flattened_weights(input_dimension, hidden_sizes, activations, weight_array)
+    }
+
+    pub fn input_dimension(&self) -> usize {
+        self.layers[0].w.shape()[0]
+    }
+
+    pub fn layer_sizes(&self) -> Vec<usize> {
+        self.layers.iter().map(|layer| layer.w.shape()[1]).collect()
+    }
+
+    pub fn activations(&self) -> Vec<Activation> {
+        self.layers.iter().map(|layer| layer.activation).collect()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, metadata: &ModelMetadata) -> Result<(), ModelError> {
+        let header = ModelHeader {
+            format_version: MODEL_FILE.version,
+            layers: self.layers
+                .iter()
+                .map(|layer| LayerHeader {
+                    num_input: layer.w.shape()[0],
+                    num_output: layer.w.shape()[1],
+                    activation: layer.activation,
+                })
+                .collect(),
+            metadata: metadata.clone(),
+        };
+        let mut body: Vec<u8> = Vec::new();
+        for weight in self.flatten_weights().iter() {
+            body.extend(&weight.to_le_bytes());
+        }
+        MODEL_FILE.write(path, &header, &body)?;
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(MLP, ModelMetadata), ModelError> {
+        let path = path.as_ref();
+        let (header, data): (ModelHeader, Vec<u8>) = MODEL_FILE.read(path).map_err(|e| match e {
+            // Bare weight arrays from before model files existed are the likeliest mix up
+            FileError::Format(message) if path.extension() == Some("npy".as_ref()) => {
+                ModelError::Format(format!("{}, is this an old .npy weights file?", message))
+            },
+            e => ModelError::from(e),
+        })?;
+        if header.layers.is_empty() {
+            return Err(ModelError::Format("model has no layers".to_string()))
+        }
+        for (i, layer) in header.layers.iter().enumerate() {
+            if layer.num_input == 0 || layer.num_output == 0 {
+                return Err(ModelError::Format(format!("layer {} has a zero size", i)))
+            }
+            if i > 0 && layer.num_input != header.layers[i - 1].num_output {
+                return Err(ModelError::Format(format!(
+                    "layer {} takes {} inputs but layer {} has {} outputs",
+                    i, layer.num_input, i - 1, header.layers[i - 1].num_output
+                )))
+            }
+        }
+
+        let expected_parameters: usize = header.layers.iter().map(|layer| layer.num_input * layer.num_output + layer.num_output).sum();
+        if data.len() % 4 != 0 || data.len() / 4 != expected_parameters {
+            return Err(ModelError::ShapeMismatch { expected_parameters, found_parameters: data.len() / 4 })
+        }
+        let weights: Vec<f32> = data.chunks(4).map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap())).collect();
+
+        let mut layers: Vec<Layer> = Vec::new();
+        let mut current_array_index: usize = 0;
+        for layer in header.layers.iter() {
+            let w_end = current_array_index + layer.num_input * layer.num_output;
+            let w = Array::from_shape_vec((layer.num_input, layer.num_output), weights[current_array_index..w_end].to_vec()).unwrap();
+            let b_end = w_end + layer.num_output;
+            let b = Array::from_shape_vec((1, layer.num_output), weights[w_end..b_end].to_vec()).unwrap();
+            current_array_index = b_end;
+
+            layers.push(Layer::from_existing_weights(w, b, layer.activation));
+        }
+
+        Ok((MLP { layers }, header.metadata))
     }
 
     pub fn flatten_weights(&self) -> Array<f32, Dim<[usize; 1]>> {
@@ -146,9 +340,10 @@ impl MLP {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
     #[test]
     fn new_layer() {
-        let layer = Layer::new(8, 4, true);
+        let layer = Layer::new(8, 4, Activation::Relu, &mut rand::thread_rng());
         assert_eq!(layer.w.len(), 32);
         assert_eq!(layer.b.len(), 4);
     }
@@ -159,7 +354,7 @@ mod tests {
         let w = Array::random((8, 4), distribution);
         let b = Array::random((1, 4), distribution);
 
-        let layer = Layer::from_existing_weights(w.clone(), b.clone(), true);
+        let layer = Layer::from_existing_weights(w.clone(), b.clone(), Activation::Relu);
 
         assert_eq!(layer.w, w);
         assert_eq!(layer.b, b);
@@ -171,7 +366,7 @@ mod tests {
         let w: Array<f32, Dim<[usize; 2]>> = Array::eye(4);
         let b: Array<f32, Dim<[usize; 2]>> = Array::zeros((1, 4));
         let sample_input = Array::ones((1, 4));
-        let layer = Layer::from_existing_weights(w, b, true);
+        let layer = Layer::from_existing_weights(w, b, Activation::Relu);
 
         let out = layer.forward(sample_input.clone());
 
@@ -180,7 +375,7 @@ mod tests {
 
     #[test]
     fn test_mlp_new() {
-        let mlp = MLP::new(4, vec![2, 2]);
+        let mlp = MLP::new(4, vec![2, 2], default_activations(2));
 
         assert_eq!(mlp.layers.len(), 2);
         assert_eq!(mlp.layers[0].w.len(), 8);
@@ -188,9 +383,9 @@ mod tests {
 
     #[test]
     fn test_mlp_from_flattened_weights() {
-        let mlp = MLP::new(4, vec![2, 2]);
+        let mlp = MLP::new(4, vec![2, 2], default_activations(2));
         let flattened_weights = mlp.flatten_weights();
-        let reconstructed_mlp = MLP::from_flattened_weights(4, vec![2, 1], flattened_weights);
+        let reconstructed_mlp = MLP::from_flattened_weights(4, vec![2, 1], default_activations(2), flattened_weights);
 
         assert_eq!(mlp.layers[0].w, reconstructed_mlp.layers[0].w);
         assert_eq!(mlp.layers[0].b, reconstructed_mlp.layers[0].b);
@@ -198,18 +393,95 @@ mod tests {
         assert_eq!(mlp.layers[1].b, reconstructed_mlp.layers[1].b);
     }
 
+    #[test]
+    fn test_legacy_activations() {
+        assert_eq!(legacy_activations(3), vec![Activation::Identity, Activation::Identity, Activation::Relu]);
+        assert_eq!(default_activations(3), vec![Activation::Relu, Activation::Relu, Activation::Identity]);
+
+        // Identity weights pass the input straight through, so only the output ReLU clips it
+        let mut weights = Vec::new();
+        for _ in 0..2 {
+            weights.extend(Array::<f32, _>::eye(2).iter());
+            weights.extend(&[0.0, 0.0]);
+        }
+        let mlp = MLP::from_flattened_weights(2, vec![2, 2], legacy_activations(2), Array::from(weights));
+        let sample_input = Array::from_shape_vec((1, 2), vec![-1.0, 2.0]).unwrap();
+        assert_eq!(mlp.forward(sample_input).into_raw_vec(), vec![0.0, 2.0]);
+    }
+
+    #[test]
+    fn test_num_parameters() {
+        let mlp = MLP::new(38, vec![16, 2], default_activations(2));
+
+        assert_eq!(MLP::num_parameters(38, &[16, 2]), mlp.flatten_weights().len());
+        assert_eq!(MLP::num_parameters(4, &[2, 1]), 13);
+    }
+
+    #[test]
+    fn test_mlp_new_using() {
+        use rand::SeedableRng;
+        let mlp_1 = MLP::new_using(4, vec![2, 2], default_activations(2), &mut rand_chacha::ChaCha8Rng::seed_from_u64(3));
+        let mlp_2 = MLP::new_using(4, vec![2, 2], default_activations(2), &mut rand_chacha::ChaCha8Rng::seed_from_u64(3));
+
+        assert_eq!(mlp_1.flatten_weights(), mlp_2.flatten_weights());
+    }
+
+    #[test]
+    fn test_mlp_save_and_load() {
+        let path = std::env::temp_dir().join("ant_sim_test_mlp_save_and_load.mlp");
+        let mlp = MLP::new(6, vec![4, 3, 2], vec![Activation::Elu, Activation::LeakyRelu, Activation::Tanh]);
+        let mut metadata = ModelMetadata { feature_schema_version: 3, ..ModelMetadata::default() };
+        metadata.training.insert("generations".to_string(), "12".to_string());
+        mlp.save(&path, &metadata).unwrap();
+        let (loaded_mlp, loaded_metadata) = MLP::load(&path).unwrap();
+
+        assert_eq!(loaded_mlp.input_dimension(), 6);
+        assert_eq!(loaded_mlp.layer_sizes(), vec![4, 3, 2]);
+        assert_eq!(loaded_mlp.activations(), mlp.activations());
+        assert_eq!(loaded_mlp.flatten_weights(), mlp.flatten_weights());
+        assert_eq!(loaded_metadata, metadata);
+        let sample_input = Array::ones((1, 6));
+        assert_eq!(loaded_mlp.forward(sample_input.clone()), mlp.forward(sample_input));
+
+        // Chopping the end off the weights has to be caught rather than misread
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+        match MLP::load(&path) {
+            Err(ModelError::ShapeMismatch { expected_parameters: 51, found_parameters: 50 }) => {},
+            _ => panic!("expected a shape mismatch"),
+        }
+        fs::write(&path, b"not a model").unwrap();
+        match MLP::load(&path) {
+            Err(ModelError::Format(_)) => {},
+            _ => panic!("expected a format error"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_mlp_forward() {
-        let mlp = MLP::new(4, vec![2, 2]);
+        let mlp = MLP::new(4, vec![2, 2], default_activations(2));
         let sample_input = Array::ones((1, 4));
         let sample_output = mlp.forward(sample_input);
 
         assert_eq!(sample_output.len(), 2);
     }
 
+    #[test]
+    fn test_from_flattened_weights_keeps_activations() {
+        let mlp = MLP::new(4, vec![3, 2], vec![Activation::Sigmoid, Activation::Tanh]);
+        let reconstructed_mlp = MLP::from_flattened_weights(4, vec![3, 2], mlp.activations(), mlp.flatten_weights());
+        let sample_input = Array::ones((1, 4)) * 10.;
+        let output = reconstructed_mlp.forward(sample_input.clone());
+
+        assert_eq!(output, mlp.forward(sample_input));
+        // A tanh output head can never ask for more than a unit turn
+        assert!(output.iter().all(|x| x.abs() <= 1.0));
+    }
+
     #[test]
     fn test_flatten_weights() {
-        let mlp = MLP::new(4, vec![2, 1]);
+        let mlp = MLP::new(4, vec![2, 1], default_activations(2));
         let flat_weights = mlp.flatten_weights();
         assert_eq!(flat_weights.len(), 16);
     }
@@ -1,4 +1,8 @@
+use std::fmt;
+use std::str::FromStr;
+
 use ndarray::{Array, Dim};
+use serde::{Deserialize, Serialize};
 
 
 pub fn relu(array: Array<f32, Dim<[usize; 2]>>) -> Array<f32, Dim<[usize; 2]>> {
@@ -14,6 +18,81 @@ pub fn relu(array: Array<f32, Dim<[usize; 2]>>) -> Array<f32, Dim<[usize; 2]>> {
 }
 
 
+// Slope of LeakyRelu below zero and the saturation value of Elu
+const LEAKY_RELU_SLOPE: f32 = 0.01;
+const ELU_ALPHA: f32 = 1.0;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Activation {
+    Identity,
+    Relu,
+    LeakyRelu,
+    Elu,
+    Tanh,
+    Sigmoid,
+    Softsign,
+}
+
+
+impl Activation {
+    pub const ALL: [Activation; 7] = [
+        Activation::Identity,
+        Activation::Relu,
+        Activation::LeakyRelu,
+        Activation::Elu,
+        Activation::Tanh,
+        Activation::Sigmoid,
+        Activation::Softsign,
+    ];
+
+    pub fn apply_scalar(&self, x: f32) -> f32 {
+        match self {
+            Activation::Identity => x,
+            Activation::Relu => if x > 0.0 { x } else { 0.0 },
+            Activation::LeakyRelu => if x > 0.0 { x } else { LEAKY_RELU_SLOPE * x },
+            Activation::Elu => if x > 0.0 { x } else { ELU_ALPHA * x.exp_m1() },
+            Activation::Tanh => x.tanh(),
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Activation::Softsign => x / (1.0 + x.abs()),
+        }
+    }
+
+    pub fn apply(&self, array: Array<f32, Dim<[usize; 2]>>) -> Array<f32, Dim<[usize; 2]>> {
+        if *self == Activation::Identity {
+            return array
+        }
+
+        array.mapv_into(|x| self.apply_scalar(x))
+    }
+}
+
+
+impl fmt::Display for Activation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+impl FromStr for Activation {
+    type Err = String;
+
+    // Accepts the names as written in config files ("LeakyRelu") as well as lower case with
+    // underscores ("leaky_relu"), which is easier to type on the command line
+    fn from_str(name: &str) -> Result<Activation, String> {
+        let normalised = name.trim().replace('_', "").to_lowercase();
+        for activation in Activation::ALL.iter() {
+            if format!("{:?}", activation).to_lowercase() == normalised {
+                return Ok(*activation)
+            }
+        }
+
+        Err(format!("unknown activation {:?}", name))
+    }
+}
+
+
 pub fn concat(arrays: &[Array<f32, Dim<[usize; 1]>>]) -> Array<f32, Dim<[usize; 1]>> {
     let mut new_array: Vec<f32> = Vec::new();
     
@@ -41,6 +120,29 @@ pub fn reshape_array(array: Array<f32, Dim<[usize; 1]>>, shape: [usize; 2]) -> A
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[test]
+    fn test_activations() {
+        let input = Array::from_shape_vec((1, 3), vec![-2.0, 0.0, 2.0]).unwrap();
+        let expected = [
+            (Activation::Identity, [-2.0, 0.0, 2.0]),
+            (Activation::Relu, [0.0, 0.0, 2.0]),
+            (Activation::LeakyRelu, [-0.02, 0.0, 2.0]),
+            (Activation::Elu, [-0.86466473, 0.0, 2.0]),
+            (Activation::Tanh, [-0.9640276, 0.0, 0.9640276]),
+            (Activation::Sigmoid, [0.11920292, 0.5, 0.880797]),
+            (Activation::Softsign, [-0.6666667, 0.0, 0.6666667]),
+        ];
+        for (activation, values) in expected.iter() {
+            let output = activation.apply(input.clone());
+            for (x, y) in output.iter().zip(values.iter()) {
+                assert!((x - y).abs() < 1e-6, "{:?}: {} != {}", activation, x, y);
+            }
+            assert_eq!(activation.to_string().parse::<Activation>(), Ok(*activation));
+        }
+        assert_eq!("leaky_relu".parse::<Activation>(), Ok(Activation::LeakyRelu));
+        assert!("swish".parse::<Activation>().is_err());
+    }
+
     #[test]
     fn test_reshape_array() {
         let arr: Array<f32, Dim<[usize; 2]>> = Array::eye(2);
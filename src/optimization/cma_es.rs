@@ -0,0 +1,280 @@
+use ndarray::{Array, Axis, Dim};
+use ndarray_rand::RandomExt;
+use ndarray_rand::rand_distr::StandardNormal;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::optimization::{Optimizer, check_fitnesses, ranking};
+use crate::simulation::utils::SimulationRng;
+
+
+// Covariance matrix adaptation (Hansen 2016, "The CMA Evolution Strategy: A Tutorial") with the
+// tutorial's default settings. Samples are drawn through a Cholesky factor of the covariance,
+// which is refactored every few generations rather than after every update. The covariance has
+// one entry per pair of parameters, so this is meant for networks with up to a few thousand
+// weights. Internally everything is kept in f64, the covariance updates are too sensitive to
+// rounding for f32.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CmaEs {
+    mean: Array<f64, Dim<[usize; 1]>>,
+    mean_f32: Array<f32, Dim<[usize; 1]>>,
+    pub sigma: f64,
+    population_size: usize,
+    weights: Array<f64, Dim<[usize; 1]>>,
+    mu_eff: f64,
+    c_c: f64,
+    c_sigma: f64,
+    c_1: f64,
+    c_mu: f64,
+    damping: f64,
+    expected_norm: f64,
+    covariance: Array<f64, Dim<[usize; 2]>>,
+    cholesky_factor: Array<f64, Dim<[usize; 2]>>,
+    factorization_interval: usize,
+    generations_since_factorization: usize,
+    evolution_path: Array<f64, Dim<[usize; 1]>>,
+    sigma_path: Array<f64, Dim<[usize; 1]>>,
+    rng: SimulationRng,
+    // The standard normal draws of the last population and the same draws shaped by the
+    // covariance, each candidate is mean + sigma * shaped sample
+    #[serde(skip)]
+    samples: Vec<Array<f64, Dim<[usize; 1]>>>,
+    #[serde(skip)]
+    shaped_samples: Vec<Array<f64, Dim<[usize; 1]>>>,
+    generation: usize,
+}
+
+
+// Lower triangular L with L L^T = matrix, or None if the matrix isn't positive definite
+fn cholesky(matrix: &Array<f64, Dim<[usize; 2]>>) -> Option<Array<f64, Dim<[usize; 2]>>> {
+    let n = matrix.nrows();
+    let mut factor: Array<f64, Dim<[usize; 2]>> = Array::zeros((n, n));
+    for j in 0..n {
+        let mut diagonal = matrix[[j, j]];
+        for k in 0..j {
+            diagonal -= factor[[j, k]] * factor[[j, k]];
+        }
+        if diagonal.is_nan() || diagonal <= 0.0 {
+            return None
+        }
+        let diagonal = diagonal.sqrt();
+        factor[[j, j]] = diagonal;
+        for i in j + 1..n {
+            let mut value = matrix[[i, j]];
+            for k in 0..j {
+                value -= factor[[i, k]] * factor[[j, k]];
+            }
+            factor[[i, j]] = value / diagonal;
+        }
+    }
+
+    Some(factor)
+}
+
+
+// Each attempt adds ten times more jitter to the diagonal than the one before, the last ones
+// add about as much as the covariance's average variance
+const MAX_FACTORIZATION_ATTEMPTS: usize = 13;
+
+
+impl CmaEs {
+    pub fn new(initial_mean: Array<f32, Dim<[usize; 1]>>, initial_sigma: f32, population_size: usize, seed: u64) -> CmaEs {
+        assert!(population_size >= 2, "population_size must be at least 2");
+        let n = initial_mean.len() as f64;
+        let mu = population_size / 2;
+        let raw_weights: Array<f64, Dim<[usize; 1]>> = (1..=mu)
+            .map(|i| (mu as f64 + 0.5).ln() - (i as f64).ln())
+            .collect();
+        let weights = &raw_weights / raw_weights.sum();
+        let mu_eff = 1. / weights.mapv(|w| w * w).sum();
+
+        let c_c = (4. + mu_eff / n) / (n + 4. + 2. * mu_eff / n);
+        let c_sigma = (mu_eff + 2.) / (n + mu_eff + 5.);
+        let c_1 = 2. / ((n + 1.3).powi(2) + mu_eff);
+        let c_mu = f64::min(1. - c_1, 2. * (mu_eff - 2. + 1. / mu_eff) / ((n + 2.).powi(2) + mu_eff));
+        let damping = 1. + 2. * f64::max(0., ((mu_eff - 1.) / (n + 1.)).sqrt() - 1.) + c_sigma;
+        let expected_norm = n.sqrt() * (1. - 1. / (4. * n) + 1. / (21. * n * n));
+        // The covariance only changes by about c_1 + c_mu each generation, so refactoring it
+        // every generation is wasted work in high dimensions
+        let factorization_interval = usize::max(1, (1. / (10. * n * (c_1 + c_mu))) as usize);
+
+        let dimension = initial_mean.len();
+        CmaEs {
+            mean: initial_mean.mapv(|x| x as f64),
+            mean_f32: initial_mean,
+            sigma: initial_sigma as f64,
+            population_size,
+            weights,
+            mu_eff,
+            c_c,
+            c_sigma,
+            c_1,
+            c_mu,
+            damping,
+            expected_norm,
+            covariance: Array::eye(dimension),
+            cholesky_factor: Array::eye(dimension),
+            factorization_interval,
+            generations_since_factorization: 0,
+            evolution_path: Array::zeros(dimension),
+            sigma_path: Array::zeros(dimension),
+            rng: SimulationRng::seed_from_u64(seed),
+            samples: Vec::new(),
+            shaped_samples: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    fn refactor_covariance(&mut self) {
+        let n = self.covariance.nrows();
+        let mut jitter = 1e-12 * self.covariance.diag().sum() / n as f64;
+        let attempts = if self.covariance.iter().all(|c| c.is_finite()) { MAX_FACTORIZATION_ATTEMPTS } else { 0 };
+        for _ in 0..attempts {
+            let mut covariance = self.covariance.clone();
+            covariance.diag_mut().mapv_inplace(|c| c + jitter);
+            if let Some(factor) = cholesky(&covariance) {
+                self.cholesky_factor = factor;
+                return
+            }
+            // Rounding can leave the covariance very slightly indefinite, nudge it back
+            jitter = f64::max(jitter * 10., 1e-12);
+        }
+        // Jitter this large would swamp the covariance anyway, and one holding NaN or inf has no
+        // usable factor, so start the search shape over instead of keeping a broken one
+        self.covariance = Array::eye(n);
+        self.cholesky_factor = Array::eye(n);
+        self.evolution_path.fill(0.);
+    }
+}
+
+
+impl Optimizer for CmaEs {
+    fn ask(&mut self) -> Vec<Array<f32, Dim<[usize; 1]>>> {
+        if self.generations_since_factorization >= self.factorization_interval {
+            self.refactor_covariance();
+            self.generations_since_factorization = 0;
+        }
+        let dimension = self.mean.len();
+        let rng = &mut self.rng;
+        self.samples = (0..self.population_size)
+            .map(|_| Array::random_using(dimension, StandardNormal, rng))
+            .collect();
+        self.shaped_samples = self.samples.iter().map(|z| self.cholesky_factor.dot(z)).collect();
+
+        self.shaped_samples
+            .iter()
+            .map(|y| (&self.mean + &(y * self.sigma)).mapv(|x| x as f32))
+            .collect()
+    }
+
+    fn tell(&mut self, fitnesses: &[f32]) {
+        check_fitnesses(self.samples.len(), fitnesses);
+        let n = self.mean.len();
+        let mu = self.weights.len();
+        let selected: Vec<usize> = ranking(fitnesses).into_iter().take(mu).collect();
+
+        // Weighted mean of the best steps, both before (z) and after (y) shaping by the covariance
+        let mut z_mean: Array<f64, Dim<[usize; 1]>> = Array::zeros(n);
+        let mut y_mean: Array<f64, Dim<[usize; 1]>> = Array::zeros(n);
+        let mut weighted_steps: Array<f64, Dim<[usize; 2]>> = Array::zeros((mu, n));
+        let mut steps: Array<f64, Dim<[usize; 2]>> = Array::zeros((mu, n));
+        for (i, index) in selected.iter().enumerate() {
+            let z = &self.samples[*index];
+            let y = &self.shaped_samples[*index];
+            z_mean.scaled_add(self.weights[i], z);
+            y_mean.scaled_add(self.weights[i], y);
+            steps.row_mut(i).assign(y);
+            weighted_steps.row_mut(i).assign(&(y * self.weights[i]));
+        }
+        self.mean.scaled_add(self.sigma, &y_mean);
+
+        // z_mean is L^-1 y_mean, standard normal (scaled by mu_eff) under random selection
+        self.sigma_path *= 1. - self.c_sigma;
+        self.sigma_path.scaled_add((self.c_sigma * (2. - self.c_sigma) * self.mu_eff).sqrt(), &z_mean);
+        let sigma_path_norm = self.sigma_path.dot(&self.sigma_path).sqrt();
+        let generations = (self.generation + 1) as i32;
+        let stalled = sigma_path_norm / (1. - (1. - self.c_sigma).powi(2 * generations)).sqrt() / self.expected_norm
+            >= 1.4 + 2. / (n as f64 + 1.);
+        let h_sigma = if stalled { 0.0 } else { 1.0 };
+
+        self.evolution_path *= 1. - self.c_c;
+        self.evolution_path.scaled_add(h_sigma * (self.c_c * (2. - self.c_c) * self.mu_eff).sqrt(), &y_mean);
+
+        let path = self.evolution_path.view().insert_axis(Axis(1));
+        let rank_one = path.dot(&path.t());
+        let rank_mu = weighted_steps.t().dot(&steps);
+        let decay = 1. - self.c_1 - self.c_mu + (1. - h_sigma) * self.c_1 * self.c_c * (2. - self.c_c);
+        self.covariance *= decay;
+        self.covariance.scaled_add(self.c_1, &rank_one);
+        self.covariance.scaled_add(self.c_mu, &rank_mu);
+
+        self.sigma *= ((self.c_sigma / self.damping) * (sigma_path_norm / self.expected_norm - 1.)).exp();
+        self.mean_f32 = self.mean.mapv(|x| x as f32);
+        self.samples.clear();
+        self.shaped_samples.clear();
+        self.generations_since_factorization += 1;
+        self.generation += 1;
+    }
+
+    fn mean(&self) -> &Array<f32, Dim<[usize; 1]>> {
+        &self.mean_f32
+    }
+
+    fn generation(&self) -> usize {
+        self.generation
+    }
+
+    fn sigma_norm(&self) -> f32 {
+        // The standard deviation of parameter i is sigma * sqrt(C_ii)
+        (self.sigma * self.covariance.diag().sum().sqrt()) as f32
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_cholesky() {
+        let matrix = Array::from_shape_vec((3, 3), vec![4., 2., 0.6, 2., 5., 1.5, 0.6, 1.5, 3.]).unwrap();
+        let factor = cholesky(&matrix).unwrap();
+
+        assert!((factor.dot(&factor.t()) - &matrix).iter().all(|x| x.abs() < 1e-12));
+        assert_eq!(factor[[0, 1]], 0.0);
+        assert!(cholesky(&Array::from_shape_vec((2, 2), vec![1., 2., 2., 1.]).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_learns_correlated_valley() {
+        // A narrow valley along x = y that an axis aligned search struggles with
+        let objective = |x: &Array<f32, Dim<[usize; 1]>>| {
+            let along = x[0] + x[1] - 2.;
+            let across = x[0] - x[1];
+            along * along + 1000. * across * across
+        };
+        let mut optimizer = CmaEs::new(Array::from(vec![-3., 4.]), 1.0, 8, 2);
+        for _ in 0..80 {
+            optimizer.step(&objective);
+        }
+
+        assert!((optimizer.mean()[0] - 1.).abs() < 1e-3 && (optimizer.mean()[1] - 1.).abs() < 1e-3);
+        // The covariance ends up stretched along the valley
+        let covariance = &optimizer.covariance;
+        assert!(covariance[[0, 1]] / (covariance[[0, 0]] * covariance[[1, 1]]).sqrt() > 0.9);
+    }
+
+    #[test]
+    fn test_unfactorable_covariance_is_reset() {
+        for value in [f64::NAN, f64::INFINITY, -1.].iter() {
+            let mut optimizer = CmaEs::new(Array::zeros(3), 1.0, 6, 0);
+            optimizer.covariance[[1, 1]] = *value;
+            optimizer.evolution_path.fill(*value);
+            optimizer.refactor_covariance();
+
+            assert_eq!(optimizer.covariance, Array::<f64, _>::eye(3));
+            assert_eq!(optimizer.cholesky_factor, Array::<f64, _>::eye(3));
+            assert!(optimizer.evolution_path.iter().all(|x| *x == 0.));
+            assert!(optimizer.ask().iter().all(|candidate| candidate.iter().all(|x| x.is_finite())));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+
+use ndarray::{Array, Dim};
+use serde::{Deserialize, Serialize};
+
+pub mod cma_es;
+pub mod openai_es;
+pub mod snes;
+
+
+// Evolution strategies for training the decision network without gradients. Every optimizer
+// works in rounds: ask() hands out a population of candidate parameter vectors, the caller
+// scores them, and tell() takes the scores in the same order and updates the search
+// distribution. Objectives are minimised, so lower fitness is better; negate a reward to
+// maximise it.
+pub trait Objective {
+    fn call(&self, x: &Array<f32, Dim<[usize; 1]>>) -> f32;
+
+    // Scores a whole population at once, override this to evaluate candidates in parallel
+    fn call_population(&self, population: &[Array<f32, Dim<[usize; 1]>>]) -> Vec<f32> {
+        population.iter().map(|x| self.call(x)).collect()
+    }
+}
+
+
+impl<F: Fn(&Array<f32, Dim<[usize; 1]>>) -> f32> Objective for F {
+    fn call(&self, x: &Array<f32, Dim<[usize; 1]>>) -> f32 {
+        self(x)
+    }
+}
+
+
+pub trait Optimizer {
+    fn ask(&mut self) -> Vec<Array<f32, Dim<[usize; 1]>>>;
+
+    // fitnesses must line up with the population returned by the last call to ask
+    fn tell(&mut self, fitnesses: &[f32]);
+
+    // The centre of the search distribution, the optimizer's current best guess
+    fn mean(&self) -> &Array<f32, Dim<[usize; 1]>>;
+
+    // Number of completed ask/tell rounds
+    fn generation(&self) -> usize;
+
+    // Length of the vector of per-parameter standard deviations of the search distribution,
+    // a single number to watch the search narrowing
+    fn sigma_norm(&self) -> f32;
+
+    fn step(&mut self, objective: &dyn Objective) -> Vec<f32> {
+        let population = self.ask();
+        let fitnesses = objective.call_population(&population);
+        self.tell(&fitnesses);
+
+        fitnesses
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OptimizerKind {
+    OpenAiEs,
+    SeparableNes,
+    CmaEs,
+}
+
+
+// Any of the optimizers, so the one chosen in the config can be checkpointed and restored
+// without knowing its type up front
+#[derive(Clone, Serialize, Deserialize)]
+pub enum EvolutionStrategy {
+    OpenAiEs(openai_es::OpenAiEs),
+    SeparableNes(snes::SeparableNes),
+    CmaEs(Box<cma_es::CmaEs>),
+}
+
+
+impl EvolutionStrategy {
+    fn optimizer(&self) -> &dyn Optimizer {
+        match self {
+            EvolutionStrategy::OpenAiEs(optimizer) => optimizer,
+            EvolutionStrategy::SeparableNes(optimizer) => optimizer,
+            EvolutionStrategy::CmaEs(optimizer) => optimizer.as_ref(),
+        }
+    }
+
+    fn optimizer_mut(&mut self) -> &mut dyn Optimizer {
+        match self {
+            EvolutionStrategy::OpenAiEs(optimizer) => optimizer,
+            EvolutionStrategy::SeparableNes(optimizer) => optimizer,
+            EvolutionStrategy::CmaEs(optimizer) => optimizer.as_mut(),
+        }
+    }
+
+    pub fn kind(&self) -> OptimizerKind {
+        match self {
+            EvolutionStrategy::OpenAiEs(_) => OptimizerKind::OpenAiEs,
+            EvolutionStrategy::SeparableNes(_) => OptimizerKind::SeparableNes,
+            EvolutionStrategy::CmaEs(_) => OptimizerKind::CmaEs,
+        }
+    }
+}
+
+
+impl Optimizer for EvolutionStrategy {
+    fn ask(&mut self) -> Vec<Array<f32, Dim<[usize; 1]>>> {
+        self.optimizer_mut().ask()
+    }
+
+    fn tell(&mut self, fitnesses: &[f32]) {
+        self.optimizer_mut().tell(fitnesses)
+    }
+
+    fn mean(&self) -> &Array<f32, Dim<[usize; 1]>> {
+        self.optimizer().mean()
+    }
+
+    fn generation(&self) -> usize {
+        self.optimizer().generation()
+    }
+
+    fn sigma_norm(&self) -> f32 {
+        self.optimizer().sigma_norm()
+    }
+}
+
+
+fn compare_fitness(a: f32, b: f32) -> Ordering {
+    // NaN fitnesses (e.g. a diverged candidate) rank below everything else
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+
+// Indices of the population from best (lowest fitness) to worst. The sort is stable so ties
+// are broken by position, keeping runs deterministic.
+pub fn ranking(fitnesses: &[f32]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..fitnesses.len()).collect();
+    order.sort_by(|a, b| compare_fitness(fitnesses[*a], fitnesses[*b]));
+
+    order
+}
+
+
+// Replaces each fitness by its rank, scaled so the best candidate gets 0.5 and the worst -0.5.
+// Only the ordering of the fitnesses matters, so a few outlying runs can't dominate an update.
+pub fn centered_ranks(fitnesses: &[f32]) -> Vec<f32> {
+    let mut utilities = vec![0.0; fitnesses.len()];
+    if fitnesses.len() < 2 {
+        return utilities
+    }
+    let scale = (fitnesses.len() - 1) as f32;
+    for (rank, index) in ranking(fitnesses).into_iter().enumerate() {
+        utilities[index] = 0.5 - rank as f32 / scale;
+    }
+
+    utilities
+}
+
+
+// The utilities used by NES, listed from best rank to worst: the top half share out positive
+// weight by log rank and everything sums to zero
+pub fn nes_utilities(population_size: usize) -> Vec<f32> {
+    let n = population_size as f32;
+    let raw: Vec<f32> = (1..=population_size)
+        .map(|rank| f32::max(0.0, (n / 2. + 1.).ln() - (rank as f32).ln()))
+        .collect();
+    let total: f32 = raw.iter().sum();
+
+    raw.iter().map(|u| u / total - 1. / n).collect()
+}
+
+
+fn check_fitnesses(expected: usize, fitnesses: &[f32]) {
+    assert!(expected > 0, "tell called before ask");
+    assert_eq!(fitnesses.len(), expected, "expected one fitness per member of the last population");
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_rank_shaping() {
+        let fitnesses = [3.0, f32::NAN, -1.0, 2.0, 2.0];
+
+        assert_eq!(ranking(&fitnesses), vec![2, 3, 4, 0, 1]);
+        assert_eq!(centered_ranks(&fitnesses), vec![-0.25, -0.5, 0.5, 0.25, 0.0]);
+
+        let utilities = nes_utilities(8);
+        assert!(utilities.iter().sum::<f32>().abs() < 1e-6);
+        assert!(utilities.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(utilities[0] > 0.0 && utilities[7] < 0.0);
+    }
+
+    #[test]
+    fn test_optimizers_minimise_sphere() {
+        let target = Array::from(vec![1.0, -2.0, 0.5, 3.0, -1.5, 0.0]);
+        let sphere = |x: &Array<f32, Dim<[usize; 1]>>| (x - &target).mapv(|d| d * d).sum();
+        let start = Array::zeros(6);
+        let mut optimizers: Vec<Box<dyn Optimizer>> = vec![
+            Box::new(openai_es::OpenAiEs::new(start.clone(), 0.1, 0.05, 40, 0)),
+            Box::new(snes::SeparableNes::new(start.clone(), 1.0, 1.0, snes::default_learning_rate_sigma(6), 20, 0)),
+            Box::new(cma_es::CmaEs::new(start, 1.0, 12, 0)),
+        ];
+
+        for optimizer in optimizers.iter_mut() {
+            let initial_sigma_norm = optimizer.sigma_norm();
+            for _ in 0..300 {
+                optimizer.step(&sphere);
+            }
+            assert_eq!(optimizer.generation(), 300);
+            assert!(sphere(optimizer.mean()) < 1e-2, "ended at {}", optimizer.mean());
+            assert!(optimizer.sigma_norm() <= initial_sigma_norm);
+        }
+    }
+}
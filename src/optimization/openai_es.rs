@@ -0,0 +1,108 @@
+use ndarray::{Array, Dim};
+use ndarray_rand::RandomExt;
+use ndarray_rand::rand_distr::StandardNormal;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::optimization::{Optimizer, centered_ranks, check_fitnesses};
+use crate::simulation::utils::SimulationRng;
+
+
+// The evolution strategy from Salimans et al. 2017, "Evolution Strategies as a Scalable
+// Alternative to Reinforcement Learning". Candidates are drawn as antithetic pairs
+// mean +/- sigma * epsilon with a fixed sigma, fitnesses are replaced by centered ranks and the
+// mean follows the resulting gradient estimate.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OpenAiEs {
+    mean: Array<f32, Dim<[usize; 1]>>,
+    pub sigma: f32,
+    pub learning_rate: f32,
+    population_size: usize,
+    rng: SimulationRng,
+    #[serde(skip)]
+    noise: Vec<Array<f32, Dim<[usize; 1]>>>,
+    generation: usize,
+}
+
+
+impl OpenAiEs {
+    pub fn new(initial_mean: Array<f32, Dim<[usize; 1]>>, sigma: f32, learning_rate: f32, population_size: usize, seed: u64) -> OpenAiEs {
+        // Candidates come in pairs, so an odd population is rounded up
+        assert!(population_size >= 2, "population_size must be at least 2");
+        OpenAiEs {
+            mean: initial_mean,
+            sigma,
+            learning_rate,
+            population_size: population_size + population_size % 2,
+            rng: SimulationRng::seed_from_u64(seed),
+            noise: Vec::new(),
+            generation: 0,
+        }
+    }
+}
+
+
+impl Optimizer for OpenAiEs {
+    fn ask(&mut self) -> Vec<Array<f32, Dim<[usize; 1]>>> {
+        let dimension = self.mean.len();
+        let rng = &mut self.rng;
+        self.noise = (0..self.population_size / 2)
+            .map(|_| Array::random_using(dimension, StandardNormal, rng))
+            .collect();
+
+        let mut population = Vec::with_capacity(self.population_size);
+        for epsilon in self.noise.iter() {
+            population.push(&self.mean + &(epsilon * self.sigma));
+            population.push(&self.mean - &(epsilon * self.sigma));
+        }
+
+        population
+    }
+
+    fn tell(&mut self, fitnesses: &[f32]) {
+        check_fitnesses(self.noise.len() * 2, fitnesses);
+        // Fitness is minimised, and centered_ranks gives the best candidate the largest utility
+        let utilities = centered_ranks(fitnesses);
+        let mut gradient: Array<f32, Dim<[usize; 1]>> = Array::zeros(self.mean.len());
+        for (pair, epsilon) in self.noise.iter().enumerate() {
+            gradient.scaled_add(utilities[2 * pair] - utilities[2 * pair + 1], epsilon);
+        }
+        let scale = self.learning_rate / (self.population_size as f32 * self.sigma);
+        self.mean.scaled_add(scale, &gradient);
+        self.noise.clear();
+        self.generation += 1;
+    }
+
+    fn mean(&self) -> &Array<f32, Dim<[usize; 1]>> {
+        &self.mean
+    }
+
+    fn generation(&self) -> usize {
+        self.generation
+    }
+
+    fn sigma_norm(&self) -> f32 {
+        self.sigma * (self.mean.len() as f32).sqrt()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_antithetic_pairs() {
+        let mut optimizer = OpenAiEs::new(Array::from(vec![1.0, 2.0, 3.0]), 0.5, 0.1, 7, 0);
+        let population = optimizer.ask();
+
+        assert_eq!(population.len(), 8);
+        for pair in population.chunks(2) {
+            assert_eq!(&(&pair[0] + &pair[1]) / 2.0, optimizer.mean());
+        }
+
+        // The better half of each pair pulls the mean towards it
+        let fitnesses: Vec<f32> = population.iter().map(|x| x[0]).collect();
+        optimizer.tell(&fitnesses);
+        assert!(optimizer.mean()[0] < 1.0);
+    }
+}
@@ -0,0 +1,126 @@
+use ndarray::{Array, Dim};
+use ndarray_rand::RandomExt;
+use ndarray_rand::rand_distr::StandardNormal;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::optimization::{Optimizer, check_fitnesses, nes_utilities, ranking};
+use crate::simulation::utils::SimulationRng;
+
+
+// Separable natural evolution strategies (Schaul et al. 2011, "High Dimensions and Heavy Tails
+// for Natural Evolution Strategies"). The search distribution is a Gaussian with its own
+// standard deviation for every parameter, which keeps each update linear in the number of
+// parameters and makes it practical for networks far too big for CMA-ES.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SeparableNes {
+    mean: Array<f32, Dim<[usize; 1]>>,
+    pub sigma: Array<f32, Dim<[usize; 1]>>,
+    pub learning_rate_mean: f32,
+    pub learning_rate_sigma: f32,
+    population_size: usize,
+    rng: SimulationRng,
+    #[serde(skip)]
+    samples: Vec<Array<f32, Dim<[usize; 1]>>>,
+    generation: usize,
+}
+
+
+// The settings recommended by the paper for a problem with this many parameters
+pub fn default_population_size(dimension: usize) -> usize {
+    4 + (3. * (dimension as f32).ln()).floor() as usize
+}
+
+
+pub fn default_learning_rate_sigma(dimension: usize) -> f32 {
+    let dimension = dimension as f32;
+    (3. + dimension.ln()) / (5. * dimension.sqrt())
+}
+
+
+impl SeparableNes {
+    pub fn new(
+        initial_mean: Array<f32, Dim<[usize; 1]>>,
+        initial_sigma: f32,
+        learning_rate_mean: f32,
+        learning_rate_sigma: f32,
+        population_size: usize,
+        seed: u64
+    ) -> SeparableNes {
+        assert!(population_size >= 2, "population_size must be at least 2");
+        let sigma = Array::from_elem(initial_mean.len(), initial_sigma);
+        SeparableNes {
+            mean: initial_mean,
+            sigma,
+            learning_rate_mean,
+            learning_rate_sigma,
+            population_size,
+            rng: SimulationRng::seed_from_u64(seed),
+            samples: Vec::new(),
+            generation: 0,
+        }
+    }
+}
+
+
+impl Optimizer for SeparableNes {
+    fn ask(&mut self) -> Vec<Array<f32, Dim<[usize; 1]>>> {
+        let dimension = self.mean.len();
+        let rng = &mut self.rng;
+        self.samples = (0..self.population_size)
+            .map(|_| Array::random_using(dimension, StandardNormal, rng))
+            .collect();
+
+        self.samples.iter().map(|sample| &self.mean + &(&self.sigma * sample)).collect()
+    }
+
+    fn tell(&mut self, fitnesses: &[f32]) {
+        check_fitnesses(self.samples.len(), fitnesses);
+        let utilities = nes_utilities(self.population_size);
+        let mut mean_gradient: Array<f32, Dim<[usize; 1]>> = Array::zeros(self.mean.len());
+        let mut sigma_gradient: Array<f32, Dim<[usize; 1]>> = Array::zeros(self.mean.len());
+        for (utility, index) in utilities.iter().zip(ranking(fitnesses)) {
+            let sample = &self.samples[index];
+            mean_gradient.scaled_add(*utility, sample);
+            sigma_gradient.scaled_add(*utility, &sample.mapv(|s| s * s - 1.));
+        }
+
+        self.mean += &(&self.sigma * &mean_gradient * self.learning_rate_mean);
+        let learning_rate_sigma = self.learning_rate_sigma;
+        self.sigma *= &sigma_gradient.mapv(|g| (learning_rate_sigma / 2. * g).exp());
+        self.samples.clear();
+        self.generation += 1;
+    }
+
+    fn mean(&self) -> &Array<f32, Dim<[usize; 1]>> {
+        &self.mean
+    }
+
+    fn generation(&self) -> usize {
+        self.generation
+    }
+
+    fn sigma_norm(&self) -> f32 {
+        self.sigma.dot(&self.sigma).sqrt()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_sigma_adapts_per_parameter() {
+        // Only the first parameter matters, so its sigma should shrink as the mean closes in
+        // while the others drift
+        let objective = |x: &Array<f32, Dim<[usize; 1]>>| x[0].abs();
+        let mut optimizer = SeparableNes::new(Array::zeros(4), 1.0, 1.0, default_learning_rate_sigma(4), 10, 1);
+        for _ in 0..100 {
+            optimizer.step(&objective);
+        }
+
+        assert!(optimizer.mean()[0].abs() < 1e-2);
+        assert!(optimizer.sigma[0] < 0.1);
+        assert!(optimizer.sigma.iter().skip(1).all(|s| *s > optimizer.sigma[0] * 10.));
+    }
+}
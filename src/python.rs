@@ -0,0 +1,419 @@
+use ndarray::Array;
+use numpy::{Element, PyArray1, PyArray2, PyArrayMethods, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rand::SeedableRng;
+
+use crate::config::{ConfigError, SimulationConfig};
+use crate::file_format::FileError;
+use crate::neural_network::mlp::{ModelError, ModelMetadata, MLP};
+use crate::neural_network::utils::Activation;
+use crate::simulation::colony::FEATURE_SCHEMA_VERSION;
+use crate::simulation::environment::{Cell, Environment};
+use crate::simulation::simulation::{Simulation, SimulationResult};
+use crate::simulation::utils::SimulationRng;
+
+
+// The Python extension module, built by maturin with the extension-module feature (see
+// pyproject.toml). Arrays cross the boundary as copies: numpy's ndarray version isn't
+// necessarily ours, and the grid isn't stored as contiguous layers anyway. Arrays handed out as
+// views of the simulation are marked read only since writing to them would change nothing.
+
+
+fn config_error(path: &str, e: ConfigError) -> PyErr {
+    let message = format!("{}: {}", path, e);
+    match e {
+        ConfigError::Io(_) => PyIOError::new_err(message),
+        _ => PyValueError::new_err(message),
+    }
+}
+
+
+fn model_error(path: &str, e: ModelError) -> PyErr {
+    let message = format!("{}: {}", path, e);
+    match e {
+        ModelError::Io(_) => PyIOError::new_err(message),
+        _ => PyValueError::new_err(message),
+    }
+}
+
+
+fn file_error(path: &str, e: FileError) -> PyErr {
+    let message = format!("{}: {}", path, e);
+    match e {
+        FileError::Io(_) => PyIOError::new_err(message),
+        _ => PyValueError::new_err(message),
+    }
+}
+
+
+// For networks made in this build rather than loaded from a file
+fn current_metadata() -> ModelMetadata {
+    ModelMetadata { feature_schema_version: FEATURE_SCHEMA_VERSION, ..ModelMetadata::default() }
+}
+
+
+fn read_only<'py, T: Element, D>(array: Bound<'py, numpy::PyArray<T, D>>) -> PyResult<Bound<'py, numpy::PyArray<T, D>>> {
+    array.getattr("flags")?.setattr("writeable", false)?;
+
+    Ok(array)
+}
+
+
+fn to_pyarray2<'py, T: Element>(py: Python<'py>, values: Vec<T>, shape: [usize; 2]) -> PyResult<Bound<'py, PyArray2<T>>> {
+    PyArray1::from_vec(py, values).reshape(shape)
+}
+
+
+fn grid_layer<'py, T: Element>(py: Python<'py>, environment: &Environment, value: fn(&Cell) -> T) -> PyResult<Bound<'py, PyArray2<T>>> {
+    // Indexed [x, y] like Environment::grid
+    let values: Vec<T> = environment.grid.iter().flatten().map(value).collect();
+
+    read_only(to_pyarray2(py, values, [environment.size, environment.size])?)
+}
+
+
+fn result_to_dict<'py>(py: Python<'py>, result: &SimulationResult) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("num_iters", result.num_iters)?;
+    dict.set_item("food_returned_to_nest", result.food_returned_to_nest)?;
+    dict.set_item("food_remaining", result.food_remaining)?;
+    dict.set_item("proportion_explored", result.proportion_explored)?;
+    dict.set_item("num_ants", result.num_ants)?;
+    dict.set_item("first_delivery_tick", result.first_delivery_tick)?;
+    dict.set_item("distance_travelled", result.distance_travelled)?;
+    dict.set_item("pheromone_deposited", result.pheromone_deposited)?;
+
+    Ok(dict)
+}
+
+
+#[pyclass(name = "MLP", module = "ant_sim")]
+#[derive(Clone)]
+pub struct PyMLP {
+    mlp: MLP,
+    // Carried along so a loaded model is saved with, and checked against, what it was trained on
+    metadata: ModelMetadata,
+}
+
+
+#[pymethods]
+impl PyMLP {
+    #[new]
+    #[pyo3(signature = (input_dimension, layer_sizes, activations=None, seed=None))]
+    fn new(input_dimension: usize, layer_sizes: Vec<usize>, activations: Option<Vec<String>>, seed: Option<u64>) -> PyResult<PyMLP> {
+        let activations: Vec<Activation> = match activations {
+            Some(names) => names
+                .iter()
+                .map(|name| name.parse::<Activation>())
+                .collect::<Result<_, _>>()
+                .map_err(PyValueError::new_err)?,
+            None => crate::neural_network::mlp::default_activations(layer_sizes.len()),
+        };
+        if layer_sizes.is_empty() || activations.len() != layer_sizes.len() {
+            return Err(PyValueError::new_err("need at least one layer and one activation per layer"))
+        }
+        let mlp = match seed {
+            Some(seed) => MLP::new_using(input_dimension, layer_sizes, activations, &mut SimulationRng::seed_from_u64(seed)),
+            None => MLP::new(input_dimension, layer_sizes, activations),
+        };
+
+        Ok(PyMLP { mlp, metadata: current_metadata() })
+    }
+
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<PyMLP> {
+        let (mlp, metadata) = MLP::load(path).map_err(|e| model_error(path, e))?;
+
+        Ok(PyMLP { mlp, metadata })
+    }
+
+    fn save(&self, path: &str) -> PyResult<()> {
+        self.mlp.save(path, &self.metadata).map_err(|e| model_error(path, e))
+    }
+
+    #[getter]
+    fn feature_schema_version(&self) -> u32 {
+        self.metadata.feature_schema_version
+    }
+
+    #[getter]
+    fn input_dimension(&self) -> usize {
+        self.mlp.input_dimension()
+    }
+
+    #[getter]
+    fn layer_sizes(&self) -> Vec<usize> {
+        self.mlp.layer_sizes()
+    }
+
+    // Named as in config files ("LeakyRelu"), which the constructor accepts as well
+    #[getter]
+    fn activations(&self) -> Vec<String> {
+        self.mlp.activations().iter().map(|activation| activation.to_string()).collect()
+    }
+
+    #[getter]
+    fn num_parameters(&self) -> usize {
+        MLP::num_parameters(self.mlp.input_dimension(), &self.mlp.layer_sizes())
+    }
+
+    // Flattened layer by layer, each layer's weight matrix in row major order followed by its
+    // biases, the same layout as the optimizers and .npy weight files use
+    fn get_weights<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f32>> {
+        PyArray1::from_vec(py, self.mlp.flatten_weights().to_vec())
+    }
+
+    fn set_weights(&mut self, weights: PyReadonlyArray1<f32>) -> PyResult<()> {
+        let weights: Vec<f32> = weights.as_array().iter().cloned().collect();
+        let num_parameters = self.num_parameters();
+        if weights.len() != num_parameters {
+            return Err(PyValueError::new_err(format!("expected {} weights, got {}", num_parameters, weights.len())))
+        }
+        self.mlp = MLP::from_flattened_weights(
+            self.mlp.input_dimension(), self.mlp.layer_sizes(), self.mlp.activations(), Array::from(weights)
+        );
+
+        Ok(())
+    }
+
+    fn forward<'py>(&self, py: Python<'py>, input: PyReadonlyArray2<f32>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        let input = input.as_array();
+        let shape = input.shape();
+        if shape[1] != self.mlp.input_dimension() {
+            return Err(PyValueError::new_err(format!(
+                "expected inputs with {} columns, got {}", self.mlp.input_dimension(), shape[1]
+            )))
+        }
+        let input = Array::from_shape_vec((shape[0], shape[1]), input.iter().cloned().collect()).unwrap();
+        let output = self.mlp.forward(input);
+        let shape = [output.shape()[0], output.shape()[1]];
+
+        to_pyarray2(py, output.iter().cloned().collect(), shape)
+    }
+}
+
+
+#[pyclass(name = "Simulation", module = "ant_sim", unsendable)]
+pub struct PySimulation {
+    config: SimulationConfig,
+    simulation: Simulation,
+    // The metadata of the colony's network, handed back with it by the network getter
+    metadata: ModelMetadata,
+}
+
+
+#[pymethods]
+impl PySimulation {
+    // From a TOML or JSON config file, or the default config, with the config's seed unless one
+    // is given. The colony starts with a randomly initialised network of the config's shape.
+    #[new]
+    #[pyo3(signature = (config=None, seed=None))]
+    fn new(config: Option<&str>, seed: Option<u64>) -> PyResult<PySimulation> {
+        let config = match config {
+            Some(path) => SimulationConfig::from_file(path).map_err(|e| config_error(path, e))?,
+            None => SimulationConfig::new(),
+        };
+        let seed = seed.unwrap_or(config.seed);
+        let simulation = Simulation::from_config(&config, config.build_network(seed), seed)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+        Ok(PySimulation { config, simulation, metadata: current_metadata() })
+    }
+
+    #[getter]
+    fn tick(&self) -> usize {
+        self.simulation.tick
+    }
+
+    #[getter]
+    fn num_steps(&self) -> usize {
+        self.config.num_steps
+    }
+
+    #[getter]
+    fn network(&self) -> PyMLP {
+        PyMLP { mlp: self.simulation.colony.decision_network().clone(), metadata: self.metadata.clone() }
+    }
+
+    #[setter]
+    fn set_network(&mut self, network: PyRef<PyMLP>) -> PyResult<()> {
+        self.config.check_network(&network.mlp, &network.metadata).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        self.simulation.colony.set_decision_network(network.mlp.clone());
+        self.metadata = network.metadata.clone();
+
+        Ok(())
+    }
+
+    #[getter]
+    fn environment(slf: Py<PySimulation>) -> PyEnvironment {
+        PyEnvironment { simulation: slf }
+    }
+
+    #[pyo3(signature = (num_steps=1))]
+    fn step(&mut self, num_steps: usize) {
+        for _ in 0..num_steps {
+            self.simulation.step();
+        }
+    }
+
+    // Runs the rest of the config's episode, or num_steps more ticks, and returns the result
+    #[pyo3(signature = (num_steps=None))]
+    fn run<'py>(&mut self, py: Python<'py>, num_steps: Option<usize>) -> PyResult<Bound<'py, PyDict>> {
+        let num_steps = num_steps.unwrap_or_else(|| self.config.num_steps.saturating_sub(self.simulation.tick));
+        let result = self.simulation.run(num_steps);
+
+        result_to_dict(py, &result)
+    }
+
+    fn save_snapshot(&self, path: &str) -> PyResult<()> {
+        self.simulation.save_snapshot(path).map_err(|e| file_error(path, e))
+    }
+
+    // Replaces the whole simulation, network included, with a saved one. The config is kept.
+    // Snapshots don't store model metadata, the network is taken to use this build's features.
+    fn load_snapshot(&mut self, path: &str) -> PyResult<()> {
+        let simulation = Simulation::load_snapshot(path).map_err(|e| file_error(path, e))?;
+        let metadata = current_metadata();
+        self.config.check_network(simulation.colony.decision_network(), &metadata)
+            .map_err(|e| PyValueError::new_err(format!("{}: {}", path, e)))?;
+        self.simulation = simulation;
+        self.metadata = metadata;
+
+        Ok(())
+    }
+
+    // Continuous ant coordinates, one (x, y) row per ant
+    fn ant_positions<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        let ants = self.simulation.colony.ants();
+        let values: Vec<f32> = ants.iter().flat_map(|ant| ant.coordinates.iter().cloned()).collect();
+
+        read_only(to_pyarray2(py, values, [ants.len(), 2])?)
+    }
+
+    // Unit heading vectors, one (x, y) row per ant
+    fn ant_directions<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        let ants = self.simulation.colony.ants();
+        let values: Vec<f32> = ants.iter().flat_map(|ant| ant.direction.iter().cloned()).collect();
+
+        read_only(to_pyarray2(py, values, [ants.len(), 2])?)
+    }
+
+    fn ants_carrying_food<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray1<bool>>> {
+        let values: Vec<bool> = self.simulation.colony.ants().iter().map(|ant| ant.has_food).collect();
+
+        read_only(PyArray1::from_vec(py, values))
+    }
+}
+
+
+// The grid of a Simulation. Each layer is read from the simulation when it's asked for, so it
+// reflects every step taken since the Environment was fetched.
+#[pyclass(name = "Environment", module = "ant_sim", unsendable)]
+pub struct PyEnvironment {
+    simulation: Py<PySimulation>,
+}
+
+
+#[pymethods]
+impl PyEnvironment {
+    #[getter]
+    fn size(&self, py: Python) -> usize {
+        self.simulation.borrow(py).simulation.environment.size
+    }
+
+    #[getter]
+    fn food_returned_to_nest(&self, py: Python) -> f64 {
+        self.simulation.borrow(py).simulation.environment.food_returned_to_nest
+    }
+
+    fn nest_pheromone<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f64>>> {
+        grid_layer(py, &self.simulation.borrow(py).simulation.environment, |cell| cell.nest_pheromone_concentration)
+    }
+
+    fn food_pheromone<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f64>>> {
+        grid_layer(py, &self.simulation.borrow(py).simulation.environment, |cell| cell.food_pheromone_concentration)
+    }
+
+    fn food<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f64>>> {
+        grid_layer(py, &self.simulation.borrow(py).simulation.environment, |cell| cell.food_amount)
+    }
+
+    fn traversable<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<bool>>> {
+        grid_layer(py, &self.simulation.borrow(py).simulation.environment, |cell| cell.is_traversable)
+    }
+
+    fn visited<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<bool>>> {
+        grid_layer(py, &self.simulation.borrow(py).simulation.environment, |cell| cell.visited)
+    }
+
+    fn nest<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<bool>>> {
+        grid_layer(py, &self.simulation.borrow(py).simulation.environment, |cell| cell.is_nest)
+    }
+}
+
+
+#[pymodule]
+fn ant_sim(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySimulation>()?;
+    m.add_class::<PyEnvironment>()?;
+    m.add_class::<PyMLP>()?;
+
+    Ok(())
+}
+
+
+#[cfg(all(test, feature = "python"))]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_simulation_and_network() {
+        Python::initialize();
+        Python::attach(|py| {
+            let mut simulation = PySimulation::new(Some("configs/maze.json"), Some(3)).unwrap();
+            simulation.step(10);
+            assert_eq!(simulation.tick(), 10);
+            let result = simulation.run(py, Some(5)).unwrap();
+            let num_iters: usize = result.get_item("num_iters").unwrap().unwrap().extract().unwrap();
+            assert_eq!(num_iters, 5);
+
+            // A loaded model keeps its metadata, and networks that don't fit are refused
+            let path = std::env::temp_dir().join("ant_sim_test_python_network.mlp");
+            let path = path.to_str().unwrap();
+            simulation.network().save(path).unwrap();
+            let network = Bound::new(py, PyMLP::load(path).unwrap()).unwrap();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(network.borrow().feature_schema_version(), FEATURE_SCHEMA_VERSION);
+            simulation.set_network(network.borrow()).unwrap();
+            let mut stale = PyMLP::new(simulation.config.input_dimension(), vec![16, 2], None, Some(1)).unwrap();
+            stale.metadata.feature_schema_version = FEATURE_SCHEMA_VERSION + 1;
+            assert!(simulation.set_network(Bound::new(py, stale).unwrap().borrow()).is_err());
+            let too_small = PyMLP::new(3, vec![2], None, None).unwrap();
+            assert!(simulation.set_network(Bound::new(py, too_small).unwrap().borrow()).is_err());
+        });
+    }
+
+    #[test]
+    fn test_mlp_weights_round_trip() {
+        Python::initialize();
+        Python::attach(|py| {
+            let mut mlp = PyMLP::new(6, vec![4, 2], Some(vec!["tanh".to_string(), "identity".to_string()]), Some(5)).unwrap();
+            let weights = mlp.get_weights(py);
+            assert_eq!(weights.len().unwrap(), mlp.num_parameters());
+
+            let doubled: Vec<f32> = weights.to_vec().unwrap().iter().map(|w| w * 2.0).collect();
+            mlp.set_weights(PyArray1::from_vec(py, doubled.clone()).readonly()).unwrap();
+            assert_eq!(mlp.get_weights(py).to_vec().unwrap(), doubled);
+            assert!(mlp.set_weights(PyArray1::from_vec(py, vec![0.0f32; 3]).readonly()).is_err());
+        });
+    }
+
+    #[test]
+    fn test_mlp_activation_names() {
+        let mlp = PyMLP::new(6, vec![4, 2], Some(vec!["tanh".to_string(), "leaky_relu".to_string()]), None).unwrap();
+        assert_eq!(mlp.activations(), vec!["Tanh", "LeakyRelu"]);
+        let rebuilt = PyMLP::new(6, vec![4, 2], Some(mlp.activations()), None).unwrap();
+        assert_eq!(rebuilt.activations(), mlp.activations());
+    }
+}
@@ -0,0 +1,199 @@
+extern crate lib;
+extern crate openblas_src;
+
+use std::sync::{Arc, Mutex};
+
+use lib::config::SimulationConfig;
+use lib::neural_network::mlp::MLP;
+use lib::simulation::render::{EpisodeRenderer, FrameRenderer};
+use lib::simulation::replay::{EpisodeRecorder, Replay, ReplayPlayer};
+use lib::simulation::simulation::Simulation;
+use lib::simulation::world_view::WorldViewSettings;
+
+
+const USAGE: &str = "Usage: render_episode --output frames/ | --output episode.gif [--config path] [--model model.mlp] \
+[--seed n] [--steps n] [--every k] [--pixel-size n] [--delay ms] [--record episode.replay | --replay episode.replay]";
+
+
+const KEYFRAME_INTERVAL: usize = 100;
+
+
+struct Args {
+    config: SimulationConfig,
+    model_path: Option<String>,
+    output_path: String,
+    seed: Option<u64>,
+    num_steps: Option<usize>,
+    interval: usize,
+    pixel_size: usize,
+    frame_delay_ms: u32,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+}
+
+
+fn exit_with_error(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| exit_with_error(format!("{} expects a number, got {:?}", flag, value)))
+}
+
+
+fn parse_args() -> Args {
+    let args: Vec<String> = std::env::args().collect();
+    let mut config: Option<SimulationConfig> = None;
+    let mut model_path: Option<String> = None;
+    let mut output_path: Option<String> = None;
+    let mut seed: Option<u64> = None;
+    let mut num_steps: Option<usize> = None;
+    let mut interval = 1;
+    let mut pixel_size = 4;
+    let mut frame_delay_ms = 40;
+    let mut record_path: Option<String> = None;
+    let mut replay_path: Option<String> = None;
+    let mut i = 1;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        if i + 1 >= args.len() {
+            exit_with_error(format!("Unrecognised argument {:?}. {}", flag, USAGE));
+        }
+        let value = &args[i + 1];
+        match flag {
+            "--config" => {
+                config = Some(SimulationConfig::from_file(value).unwrap_or_else(|e| {
+                    exit_with_error(format!("{}: {}", value, e))
+                }));
+            },
+            "--model" => model_path = Some(value.clone()),
+            "--output" => output_path = Some(value.clone()),
+            "--seed" => seed = Some(parse_number(flag, value)),
+            "--steps" => num_steps = Some(parse_number(flag, value)),
+            "--every" => interval = parse_number(flag, value),
+            "--pixel-size" => pixel_size = parse_number(flag, value),
+            "--delay" => frame_delay_ms = parse_number(flag, value),
+            "--record" => record_path = Some(value.clone()),
+            "--replay" => replay_path = Some(value.clone()),
+            _ => exit_with_error(format!("Unrecognised argument {:?}. {}", flag, USAGE)),
+        }
+        i += 2;
+    }
+    if interval == 0 || pixel_size == 0 {
+        exit_with_error("--every and --pixel-size must be at least 1".to_string());
+    }
+    if record_path.is_some() && replay_path.is_some() {
+        exit_with_error(format!("--record and --replay can't be used together. {}", USAGE));
+    }
+
+    Args {
+        config: config.unwrap_or_default(),
+        model_path,
+        output_path: output_path.unwrap_or_else(|| exit_with_error(format!("--output is required. {}", USAGE))),
+        seed,
+        num_steps,
+        interval,
+        pixel_size,
+        frame_delay_ms,
+        record_path,
+        replay_path,
+    }
+}
+
+
+fn load_model(config: &mut SimulationConfig, model_path: &Option<String>, seed: u64) -> MLP {
+    let model_path = match model_path {
+        Some(model_path) => model_path,
+        None => {
+            println!("No --model given, rendering a randomly initialised network");
+            return config.build_network(seed)
+        },
+    };
+    let (decision_network, metadata) = MLP::load(model_path).unwrap_or_else(|e| {
+        exit_with_error(format!("{}: {}", model_path, e))
+    });
+    if let Err(e) = config.check_network(&decision_network, &metadata) {
+        exit_with_error(format!("{}: {}", model_path, e));
+    }
+    config.network.hidden_sizes = decision_network.layer_sizes();
+    config.network.activations = Some(decision_network.activations());
+
+    decision_network
+}
+
+
+// The starting state, then every k ticks
+fn render_first_frame(episode_renderer: &Mutex<EpisodeRenderer>, simulation: &Simulation, output_path: &str) {
+    if let Err(e) = episode_renderer.lock().unwrap().render_frame(simulation) {
+        exit_with_error(format!("{}: {}", output_path, e));
+    }
+}
+
+
+fn main() {
+    let mut args = parse_args();
+    let settings = WorldViewSettings { pixel_size: args.pixel_size, ..WorldViewSettings::new() };
+    let renderer = FrameRenderer::new(settings);
+    // Anything that isn't a .gif is a directory of PNG frames
+    let is_gif = args.output_path.to_lowercase().ends_with(".gif");
+    let episode_renderer = if is_gif {
+        EpisodeRenderer::gif(renderer, &args.output_path, args.interval, args.frame_delay_ms)
+    } else {
+        EpisodeRenderer::png_frames(renderer, &args.output_path, args.interval)
+    };
+    let episode_renderer = Arc::new(Mutex::new(episode_renderer.unwrap_or_else(|e| {
+        exit_with_error(format!("{}: {}", args.output_path, e))
+    })));
+
+    let result = match &args.replay_path {
+        // The config, model and seed come from the recording, which is played from its start
+        Some(replay_path) => {
+            let replay = Replay::load(replay_path).unwrap_or_else(|e| exit_with_error(format!("{}: {}", replay_path, e)));
+            let mut player = ReplayPlayer::new(replay);
+            render_first_frame(&episode_renderer, player.simulation(), &args.output_path);
+            player.add_observer(Box::new(episode_renderer.clone()));
+            let num_steps = args.num_steps.unwrap_or(usize::MAX);
+            let mut i = 0;
+            while i < num_steps && player.step() {
+                i += 1;
+            }
+            player.finish()
+        },
+        None => {
+            let seed = args.seed.unwrap_or(args.config.seed);
+            let decision_network = load_model(&mut args.config, &args.model_path, seed);
+            let num_steps = args.num_steps.unwrap_or(args.config.num_steps);
+            let mut simulation = Simulation::from_config(&args.config, decision_network, seed).unwrap_or_else(|e| {
+                exit_with_error(e.to_string())
+            });
+            render_first_frame(&episode_renderer, &simulation, &args.output_path);
+            simulation.add_observer(Box::new(episode_renderer.clone()));
+            let recorder = args.record_path.as_ref().map(|_| {
+                Arc::new(Mutex::new(EpisodeRecorder::new(&simulation, Some(args.config.clone()), seed, KEYFRAME_INTERVAL)))
+            });
+            if let Some(recorder) = &recorder {
+                simulation.add_observer(Box::new(recorder.clone()));
+            }
+            let result = simulation.run(num_steps);
+            if let (Some(recorder), Some(record_path)) = (&recorder, &args.record_path) {
+                if let Err(e) = recorder.lock().unwrap().replay().save(record_path) {
+                    exit_with_error(format!("{}: {}", record_path, e));
+                }
+                println!("Recorded {} ticks to {}", result.num_iters, record_path);
+            }
+            result
+        },
+    };
+
+    // Every other reference to the renderer was dropped with the simulation
+    let episode_renderer = Arc::try_unwrap(episode_renderer).ok().unwrap().into_inner().unwrap();
+    match episode_renderer.finish() {
+        Ok(frames_written) => println!(
+            "Wrote {} frames of {} ticks to {}, food returned {:.1}",
+            frames_written, result.num_iters, args.output_path, result.food_returned_to_nest
+        ),
+        Err(e) => exit_with_error(format!("{}: {}", args.output_path, e)),
+    }
+}
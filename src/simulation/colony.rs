@@ -1,14 +1,32 @@
-use ndarray::{Array, Dim};
+use ndarray::{Array, Axis, Dim, concatenate};
 use piston::input::GenericEvent;
-use rand;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::simulation::environment::{Cell, Environment};
-use crate::simulation::utils::{get_direction_from_coords, random_unit_vector, random_rotation, normalize_array, rotate_array2};
+use crate::simulation::utils::{
+    get_direction_from_coords, random_unit_vector, random_rotation, normalize_array, rotate_array2, SimulationRng
+};
 use crate::neural_network::mlp::MLP;
 
 
 
-#[derive(Clone)]
+// Bump this whenever the layout of Ant::get_feature_vector changes, so that saved models
+// trained on the old layout are refused rather than fed the wrong inputs
+pub const FEATURE_SCHEMA_VERSION: u32 = 2;
+
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AntSettings {
+    pub velocity: f32,
+    pub max_perception_distance: f32,
+    pub field_of_view: f32,
+    pub num_samples: usize,
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Ant {
     pub coordinates: Array<f32, Dim<[usize; 1]>>,
     pub direction: Array<f32, Dim<[usize; 1]>>,
@@ -17,31 +35,103 @@ pub struct Ant {
     pub field_of_view: f32,
     pub grid_location: [usize; 2],
     pub has_food: bool,
+    // How far the ant has walked, a measure of the energy it has spent
+    pub distance_travelled: f32,
     num_samples: usize,
 }
 
 
+// Something that happened to a single ant during a colony update. Ants are identified by their
+// index in Colony::ants and positions are the ant's continuous coordinates at the time.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AntEvent {
+    FoodPickedUp { ant: usize, position: [f32; 2] },
+    FoodDelivered { ant: usize, position: [f32; 2] },
+    // The ant tried to walk into an obstacle or off the arena, whether or not it then found
+    // another way
+    Blocked { ant: usize, position: [f32; 2] },
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Colony {
     ants: Vec<Ant>,
     decision_network: MLP,
+    events: Vec<AntEvent>,
 }
 
 
-impl Ant {
-    pub fn new() -> Ant {
-        Ant {
-            coordinates: Array::from(vec![1.0, 1.0]),
-            direction: random_unit_vector(),
+impl AntSettings {
+    pub fn new() -> AntSettings {
+        AntSettings {
             velocity: 0.5,
             max_perception_distance: 10.0,
             field_of_view: std::f32::consts::PI / 1.0,
+            num_samples: 5,
+        }
+    }
+
+    pub fn feature_dimension(&self) -> usize {
+        // 3 channels for the ant itself and 7 for every sampled cell, see Ant::get_feature_vector
+        3 + 7 * self.num_samples
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        // Ants moving a whole cell or more per tick could step straight over a wall
+        if !(self.velocity > 0.0 && self.velocity < 1.0) {
+            return Err(format!("velocity must be in (0, 1), got {}", self.velocity))
+        }
+        if !(self.max_perception_distance > 0.0) {
+            return Err(format!("max_perception_distance must be positive, got {}", self.max_perception_distance))
+        }
+        if !(self.field_of_view > 0.0 && self.field_of_view <= 2. * std::f32::consts::PI) {
+            return Err(format!("field_of_view must be in (0, 2 pi], got {}", self.field_of_view))
+        }
+        if self.num_samples == 0 {
+            return Err("num_samples must be at least 1".to_string())
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Default for AntSettings {
+    fn default() -> AntSettings {
+        AntSettings::new()
+    }
+}
+
+
+impl Ant {
+    pub fn new(rng: &mut SimulationRng) -> Ant {
+        Ant::with_settings(&AntSettings::new(), rng)
+    }
+
+    pub fn with_settings(ant_settings: &AntSettings, rng: &mut SimulationRng) -> Ant {
+        Ant {
+            coordinates: Array::from(vec![1.0, 1.0]),
+            direction: random_unit_vector(rng),
+            velocity: ant_settings.velocity,
+            max_perception_distance: ant_settings.max_perception_distance,
+            field_of_view: ant_settings.field_of_view,
             grid_location: [1; 2],
             has_food: false,
-            num_samples: 5,
+            distance_travelled: 0.0,
+            num_samples: ant_settings.num_samples,
         }
     }
 
-    fn update_position(&mut self, environment: &Environment) {
+    pub fn at_location(grid_location: [usize; 2], ant_settings: &AntSettings, rng: &mut SimulationRng) -> Ant {
+        let mut ant = Ant::with_settings(ant_settings, rng);
+        ant.coordinates = Array::from(vec![grid_location[0] as f32 + 0.5, grid_location[1] as f32 + 0.5]);
+        ant.grid_location = grid_location;
+
+        ant
+    }
+
+    // Returns whether any attempted move was blocked
+    fn update_position(&mut self, environment: &Environment, rng: &mut SimulationRng) -> bool {
         let mut new_coordinates: Array<f32, Dim<[usize; 1]>>;
         let mut new_grid_cell_indices: [usize; 2];
         let mut i = 0;
@@ -53,20 +143,27 @@ impl Ant {
             if environment.cell_is_traversable(new_grid_cell_indices) {
                 self.coordinates = new_coordinates;
                 self.grid_location = new_grid_cell_indices;
+                self.distance_travelled += self.velocity;
                 break;
             } else {
-                self.direction = random_rotation(&self.direction, 2. * std::f32::consts::PI);
+                self.direction = random_rotation(&self.direction, 2. * std::f32::consts::PI, rng);
             }
             i += 1;
         }
+
+        i > 0
+    }
+
+    fn position(&self) -> [f32; 2] {
+        [self.coordinates[0], self.coordinates[1]]
     }
 
-    fn perceive_surroundings(&self, environment: &Environment) -> Vec<Cell> {
+    fn perceive_surroundings(&self, environment: &Environment, rng: &mut SimulationRng) -> Vec<Cell> {
         let mut surroundings: Vec<Cell> = Vec::new();
         // Take 10 samples of the surroundings, maybe make the sample size tunable in future
         while surroundings.len() < self.num_samples {
-            let random_direction = random_rotation(&self.direction, self.field_of_view);
-            let random_distance = rand::random::<f32>() * self.max_perception_distance;
+            let random_direction = random_rotation(&self.direction, self.field_of_view, rng);
+            let random_distance = rng.gen::<f32>() * self.max_perception_distance;
             let sample_point = self.coordinates.clone() + random_direction * random_distance;
             match environment.get_cell_from_point(&sample_point) {
                 Ok(cell) => surroundings.push(cell),
@@ -94,8 +191,8 @@ impl Ant {
         surroundings
     }
 
-    fn get_feature_vector(&self, environment: &Environment) -> Array<f32, Dim<[usize; 2]>> {
-        let surroundings = self.perceive_surroundings(environment);
+    fn get_feature_vector(&self, environment: &Environment, rng: &mut SimulationRng) -> Array<f32, Dim<[usize; 2]>> {
+        let surroundings = self.perceive_surroundings(environment, rng);
         let mut feature_vec: Vec<f32> = Vec::new();
         // let current_cell = environment.get_cell_from_point(&self.coordinates).unwrap();
         // First 3 channels are the ant's personal info
@@ -104,8 +201,13 @@ impl Ant {
         feature_vec.push(self.direction[[1]]);
         
         for cell in surroundings.iter() {
-            let coords_as_array = Array::from(self.coordinates.clone());
-            let direction_to_cell = get_direction_from_coords(&self.coordinates, &coords_as_array);
+            // An ant standing exactly on the cell's location has no direction to it
+            let cell_location = cell.get_continuous_location();
+            let direction_to_cell = if cell_location == self.coordinates {
+                Array::zeros(2)
+            } else {
+                get_direction_from_coords(&self.coordinates, &cell_location)
+            };
             feature_vec.push(cell.is_nest as i32 as f32); // Have to go through int to get to f32 from bool
             feature_vec.push(cell.is_traversable as i32 as f32);
             feature_vec.push(cell.food_amount as f32);
@@ -118,7 +220,7 @@ impl Ant {
         Array::from_shape_vec((1, feature_vec.len()), feature_vec).unwrap()
     }
 
-    fn _update_direction(&mut self, surroundings: Vec<Cell>) {
+    fn _update_direction(&mut self, surroundings: Vec<Cell>, rng: &mut SimulationRng) {
         // The old hard coded version that only kinda works. Worth keeping for the time being for reference and to
         // compare to old behavior.
         if self.has_food {
@@ -139,7 +241,7 @@ impl Ant {
                 self.direction = normalize_array(point_difference);
 
             } else {
-                self.direction = random_rotation(&self.direction, 0.5);
+                self.direction = random_rotation(&self.direction, 0.5, rng);
             }
         } else {
             // If the ant does not have food, go towards food directly if it's visible, otherwise
@@ -161,33 +263,42 @@ impl Ant {
                 let point_difference = max_cell_point - self.coordinates.clone();
                 self.direction = normalize_array(point_difference);
             } else {
-                self.direction = random_rotation(&self.direction, 0.5);
+                self.direction = random_rotation(&self.direction, 0.5, rng);
             }
         }
     }
 
-    fn update_direction(&mut self, environment: &Environment, decision_netowrk: &MLP) {
-        let feature_vector = self.get_feature_vector(environment);
-        let network_output = decision_netowrk.forward(feature_vector);
-        let flat_network_output = Array::from_iter(network_output.iter().cloned());
-        self.direction = rotate_array2(&self.direction, flat_network_output[0]);
+    fn update_direction(&mut self, turn: f32) {
+        self.direction = rotate_array2(&self.direction, turn);
         // direction_vector = random_rotation(&direction_vector, 0.005);
 
         //self.direction = normalize_array(direction_vector);
     }
 
-    fn update(&mut self, environment: &mut Environment, decision_netowrk: &MLP) {
-        self.update_position(environment);
+    fn update(
+        &mut self,
+        id: usize,
+        environment: &mut Environment,
+        events: &mut Vec<AntEvent>,
+        rng: &mut SimulationRng
+    ) -> Array<f32, Dim<[usize; 2]>> {
+        // Moves the ant and lets it interact with its new cell, then returns what it perceives
+        // there for the decision network. The network's output is applied by update_direction.
+        if self.update_position(environment, rng) {
+            events.push(AntEvent::Blocked { ant: id, position: self.position() });
+        }
         if environment.cell_has_food(self.grid_location) && !self.has_food {
             environment.take_food(self.grid_location);
             //self.direction *= -1.0;
             self.has_food = true;
+            events.push(AntEvent::FoodPickedUp { ant: id, position: self.position() });
         }
 
         if environment.cell_is_nest(self.grid_location) && self.has_food {
             //self.direction *= -1.0;
             environment.food_returned_to_nest += 0.1;
             self.has_food = false;
+            events.push(AntEvent::FoodDelivered { ant: id, position: self.position() });
         }
 
         if self.has_food {
@@ -196,51 +307,219 @@ impl Ant {
             environment.place_nest_pheromone(self.grid_location);
         }
 
-        self.update_direction(environment, decision_netowrk);
+        self.get_feature_vector(environment, rng)
     }
 }
 
 
 impl Colony {
-    pub fn new(num_ants: usize, decision_network: MLP) -> Colony {
+    pub fn new(num_ants: usize, decision_network: MLP, rng: &mut SimulationRng) -> Colony {
+        Colony {
+            ants: vec![Ant::new(rng); num_ants],
+            decision_network,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn at_location(
+        num_ants: usize,
+        decision_network: MLP,
+        grid_location: [usize; 2],
+        ant_settings: &AntSettings,
+        rng: &mut SimulationRng
+    ) -> Colony {
+        Colony {
+            ants: vec![Ant::at_location(grid_location, ant_settings, rng); num_ants],
+            decision_network,
+            events: Vec::new(),
+        }
+    }
+
+    // A colony of ants that already exist, such as ones restored from a replay's keyframe
+    pub fn from_ants(ants: Vec<Ant>, decision_network: MLP) -> Colony {
         Colony {
-            ants: vec![Ant::new(); num_ants],
+            ants,
             decision_network,
+            events: Vec::new(),
         }
     }
 
-    pub fn update(&mut self, environment: &mut Environment) {
-        for ant in self.ants.iter_mut() {
-            ant.update(environment, &self.decision_network);
+    pub fn ants(&self) -> &[Ant] {
+        &self.ants
+    }
+
+    pub fn decision_network(&self) -> &MLP {
+        &self.decision_network
+    }
+
+    pub fn set_decision_network(&mut self, decision_network: MLP) {
+        self.decision_network = decision_network;
+    }
+
+    // What happened to the ants during the most recent update
+    pub fn events(&self) -> &[AntEvent] {
+        &self.events
+    }
+
+    pub fn distance_travelled(&self) -> f64 {
+        self.ants.iter().map(|ant| ant.distance_travelled as f64).sum()
+    }
+
+    // What every ant perceives from where it stands, one row per ant, without moving any of them
+    pub fn observe(&self, environment: &Environment, rng: &mut SimulationRng) -> Array<f32, Dim<[usize; 2]>> {
+        let feature_vectors: Vec<Array<f32, Dim<[usize; 2]>>> = self.ants
+            .iter()
+            .map(|ant| ant.get_feature_vector(environment, rng))
+            .collect();
+
+        stack_feature_vectors(&feature_vectors)
+    }
+
+    // Moves every ant along its current direction and returns what each one perceives
+    // afterwards, one row per ant. Ants move and perceive one after the other, so each one sees
+    // the pheromone left by the ants before it this tick.
+    pub fn move_ants(&mut self, environment: &mut Environment, rng: &mut SimulationRng) -> Array<f32, Dim<[usize; 2]>> {
+        self.events.clear();
+        let mut feature_vectors: Vec<Array<f32, Dim<[usize; 2]>>> = Vec::with_capacity(self.ants.len());
+        for (id, ant) in self.ants.iter_mut().enumerate() {
+            feature_vectors.push(ant.update(id, environment, &mut self.events, rng));
             environment.set_cell_as_visited(ant.grid_location);
         }
+
+        stack_feature_vectors(&feature_vectors)
+    }
+
+    // Turns each ant by the matching angle in radians, which takes effect on its next move
+    pub fn turn_ants(&mut self, turns: &[f32]) {
+        assert_eq!(turns.len(), self.ants.len(), "expected one turn per ant");
+        for (ant, turn) in self.ants.iter_mut().zip(turns.iter()) {
+            ant.update_direction(*turn);
+        }
+    }
+
+    // The direction each ant will move in on its next move, as unit vectors
+    pub fn directions(&self) -> Vec<[f32; 2]> {
+        self.ants.iter().map(|ant| [ant.direction[0], ant.direction[1]]).collect()
     }
 
-    pub fn update_piston<E: GenericEvent>(&mut self, environment: &mut Environment, e: &E) {
+    pub fn set_directions(&mut self, directions: &[[f32; 2]]) {
+        assert_eq!(directions.len(), self.ants.len(), "expected one direction per ant");
+        for (ant, direction) in self.ants.iter_mut().zip(directions.iter()) {
+            ant.direction = Array::from(direction.to_vec());
+        }
+    }
+
+    pub fn update(&mut self, environment: &mut Environment, rng: &mut SimulationRng) {
+        if self.ants.is_empty() {
+            self.events.clear();
+            return
+        }
+        // Decisions only depend on each ant's own features, so all of them can go through the
+        // network as one N x F batch
+        let features = self.move_ants(environment, rng);
+
+        let network_output = self.decision_network.forward(features);
+        for (ant, output) in self.ants.iter_mut().zip(network_output.rows()) {
+            ant.update_direction(output[0]);
+        }
+    }
+
+    pub fn update_piston<E: GenericEvent>(&mut self, environment: &mut Environment, e: &E, rng: &mut SimulationRng) {
         if let Some(_) = e.update_args() {
-            self.update(environment);
+            self.update(environment, rng);
         }
     }
 }
 
 
+fn stack_feature_vectors(feature_vectors: &[Array<f32, Dim<[usize; 2]>>]) -> Array<f32, Dim<[usize; 2]>> {
+    if feature_vectors.is_empty() {
+        return Array::zeros((0, 0))
+    }
+    let feature_views: Vec<_> = feature_vectors.iter().map(|feature_vector| feature_vector.view()).collect();
+
+    concatenate(Axis(0), &feature_views).unwrap()
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::neural_network::mlp::default_activations;
+    use crate::neural_network::utils::Activation;
+    use rand::SeedableRng;
     #[test]
     fn test_perceive_surroundings() {
+        let mut rng = SimulationRng::seed_from_u64(0);
         let environment = Environment::new(50, 0.99);
-        let ant = Ant::new();
-        let surroundings = ant.perceive_surroundings(&environment);
+        let ant = Ant::new(&mut rng);
+        let surroundings = ant.perceive_surroundings(&environment, &mut rng);
         
         assert_eq!(surroundings.len(), 5);
     }
     #[test]
     fn test_ant_get_feature_vector() {
+        let mut rng = SimulationRng::seed_from_u64(0);
         let mut environment = Environment::new(50, 0.99);
-        let ant = Ant::new();
-        let feature_vector = ant.get_feature_vector(&mut environment);
+        let ant = Ant::new(&mut rng);
+        let feature_vector = ant.get_feature_vector(&mut environment, &mut rng);
 
         assert_eq!(feature_vector.len(), 38);
+        assert_eq!(feature_vector.len(), AntSettings::new().feature_dimension());
+        assert!(feature_vector.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn test_ants_move_without_relu_first_layer() {
+        // ReLU maps NaN to 0, so only other activations show NaN features reaching the network
+        let ant_settings = AntSettings::new();
+        let activations = vec![Activation::Elu, Activation::Tanh];
+        let mut network_rng = SimulationRng::seed_from_u64(1);
+        let decision_network = MLP::new_using(ant_settings.feature_dimension(), vec![16, 2], activations, &mut network_rng);
+        let mut rng = SimulationRng::seed_from_u64(2);
+        let mut environment = Environment::new(30, 0.99);
+        let mut colony = Colony::at_location(10, decision_network, [15, 15], &ant_settings, &mut rng);
+
+        for _ in 0..20 {
+            colony.update(&mut environment, &mut rng);
+        }
+        for ant in colony.ants() {
+            assert!(ant.direction.iter().all(|x| x.is_finite()));
+            assert!(ant.coordinates.iter().all(|x| x.is_finite()));
+            assert!(ant.distance_travelled > 0.0);
+        }
+        assert!(colony.ants().iter().any(|ant| ant.grid_location != [15, 15]));
+    }
+
+    #[test]
+    fn test_batched_update_matches_sequential() {
+        let ant_settings = AntSettings::new();
+        let decision_network = MLP::new(ant_settings.feature_dimension(), vec![16, 2], default_activations(2));
+        let mut rng = SimulationRng::seed_from_u64(4);
+        let mut environment = Environment::new(30, 0.99);
+        let mut colony = Colony::at_location(50, decision_network.clone(), [3, 3], &ant_settings, &mut rng);
+        let mut sequential_rng = rng.clone();
+        let mut sequential_environment = environment.clone();
+        let mut sequential_ants = colony.ants.clone();
+
+        for _ in 0..20 {
+            colony.update(&mut environment, &mut rng);
+            // One forward pass per ant, straight after it moves
+            let mut sequential_events = Vec::new();
+            for (id, ant) in sequential_ants.iter_mut().enumerate() {
+                let feature_vector = ant.update(id, &mut sequential_environment, &mut sequential_events, &mut sequential_rng);
+                let network_output = decision_network.forward(feature_vector);
+                ant.update_direction(network_output[[0, 0]]);
+                sequential_environment.set_cell_as_visited(ant.grid_location);
+            }
+            assert_eq!(colony.events(), &sequential_events[..]);
+        }
+
+        for (ant, sequential_ant) in colony.ants.iter().zip(sequential_ants.iter()) {
+            assert_eq!(ant.grid_location, sequential_ant.grid_location);
+            assert!((&ant.coordinates - &sequential_ant.coordinates).iter().all(|x| x.abs() < 1e-4));
+            assert!((&ant.direction - &sequential_ant.direction).iter().all(|x| x.abs() < 1e-4));
+        }
+        assert_eq!(environment.total_food_pheromone(), sequential_environment.total_food_pheromone());
     }
 }
\ No newline at end of file
@@ -0,0 +1,286 @@
+use piston::input::{Button, GenericEvent, Key};
+
+use crate::config::SimulationConfig;
+use crate::neural_network::mlp::MLP;
+use crate::simulation::environment::Environment;
+use crate::simulation::map::MapError;
+use crate::simulation::replay::ReplayPlayer;
+use crate::simulation::simulation::Simulation;
+
+
+// Keyboard controls for running a simulation interactively:
+//   Space       pause / resume
+//   Right       advance a single tick while paused
+//   Up / Down   double / halve the number of updates per second
+//   R           reset the world with the next seed
+pub struct SimulationController {
+    pub simulation: Simulation,
+    pub paused: bool,
+    pub ups: u64,
+    pub seed: u64,
+    config: SimulationConfig,
+    // The world as built from the config, resets start from a copy of it
+    environment: Environment,
+    decision_network: MLP,
+}
+
+
+const MIN_UPS: u64 = 1;
+const MAX_UPS: u64 = 1600;
+
+
+impl SimulationController {
+    pub fn new(config: SimulationConfig, decision_network: MLP, ups: u64) -> Result<SimulationController, MapError> {
+        let seed = config.seed;
+        let environment = config.environment.build()?;
+        let simulation = Simulation::from_environment(
+            environment.clone(), config.colony.num_ants, &config.ant, decision_network.clone(), seed
+        );
+
+        Ok(SimulationController {
+            simulation,
+            paused: false,
+            ups,
+            seed,
+            config,
+            environment,
+            decision_network,
+        })
+    }
+
+    pub fn reset(&mut self, seed: u64) {
+        self.seed = seed;
+        self.simulation = Simulation::from_environment(
+            self.environment.clone(), self.config.colony.num_ants, &self.config.ant, self.decision_network.clone(), seed
+        );
+    }
+
+    // Returns a message describing what the key did, if it did anything
+    pub fn key_pressed(&mut self, key: Key) -> Option<String> {
+        match key {
+            Key::Space => {
+                self.paused = !self.paused;
+                Some(format!("{} at tick {}", if self.paused { "Paused" } else { "Resumed" }, self.simulation.tick))
+            },
+            Key::Right if self.paused => {
+                self.simulation.step();
+                Some(format!("Tick {}", self.simulation.tick))
+            },
+            Key::Up => {
+                self.ups = u64::min(self.ups * 2, MAX_UPS);
+                Some(format!("Updates per second: {}", self.ups))
+            },
+            Key::Down => {
+                self.ups = u64::max(self.ups / 2, MIN_UPS);
+                Some(format!("Updates per second: {}", self.ups))
+            },
+            Key::R => {
+                self.reset(self.seed.wrapping_add(1));
+                Some(format!("Reset with seed {}", self.seed))
+            },
+            _ => None,
+        }
+    }
+
+    // Returns the message for a key press, for the caller to show
+    pub fn event<E: GenericEvent>(&mut self, e: &E) -> Option<String> {
+        let mut message = None;
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            message = self.key_pressed(key);
+        }
+        if !self.paused {
+            self.simulation.update_piston(e);
+        }
+
+        message
+    }
+}
+
+
+// The same controls for playing back a recorded episode, except that the world can't be reset:
+//   Space       pause / resume
+//   Left/Right  step back / forward a single tick while paused
+//   Up / Down   double / halve the number of updates per second
+//   [ / ]       jump back / forward by one keyframe interval
+//   R           restart from the beginning
+pub struct ReplayController {
+    pub player: ReplayPlayer,
+    pub paused: bool,
+    pub ups: u64,
+}
+
+
+impl ReplayController {
+    pub fn new(player: ReplayPlayer, ups: u64) -> ReplayController {
+        ReplayController {
+            player,
+            paused: false,
+            ups,
+        }
+    }
+
+    fn seek(&mut self, tick: usize) -> Option<String> {
+        self.player.seek(tick);
+        Some(format!("Tick {}", self.player.tick()))
+    }
+
+    // Returns a message describing what the key did, if it did anything
+    pub fn key_pressed(&mut self, key: Key) -> Option<String> {
+        let tick = self.player.tick();
+        let keyframe_interval = self.player.replay().header().keyframe_interval;
+        match key {
+            Key::Space => {
+                self.paused = !self.paused;
+                Some(format!("{} at tick {}", if self.paused { "Paused" } else { "Resumed" }, tick))
+            },
+            Key::Right if self.paused => self.seek(tick + 1),
+            Key::Left if self.paused => self.seek(tick.saturating_sub(1)),
+            Key::RightBracket => self.seek(tick + keyframe_interval),
+            Key::LeftBracket => self.seek(tick.saturating_sub(keyframe_interval)),
+            Key::Up => {
+                self.ups = u64::min(self.ups * 2, MAX_UPS);
+                Some(format!("Updates per second: {}", self.ups))
+            },
+            Key::Down => {
+                self.ups = u64::max(self.ups / 2, MIN_UPS);
+                Some(format!("Updates per second: {}", self.ups))
+            },
+            Key::R => self.seek(0),
+            _ => None,
+        }
+    }
+
+    // Returns the message for a key press, or for reaching the end of the replay, for the caller
+    // to show
+    pub fn event<E: GenericEvent>(&mut self, e: &E) -> Option<String> {
+        let mut message = None;
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            message = self.key_pressed(key);
+        }
+        if !self.paused && e.update_args().is_some() && !self.player.step() {
+            self.paused = true;
+            message = Some(format!("End of the replay at tick {}", self.player.tick()));
+        }
+
+        message
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::replay::EpisodeRecorder;
+    use piston::input::{ButtonArgs, ButtonState, Event, Input, Loop, UpdateArgs};
+
+    fn press(key: Key) -> Event {
+        Event::Input(Input::Button(ButtonArgs {
+            state: ButtonState::Press,
+            button: Button::Keyboard(key),
+            scancode: None,
+        }), None)
+    }
+
+    fn update() -> Event {
+        Event::Loop(Loop::Update(UpdateArgs { dt: 0.01 }))
+    }
+
+    fn controller() -> SimulationController {
+        let mut config = SimulationConfig::new();
+        config.environment.arena_size = 20;
+        config.colony.num_ants = 5;
+        let decision_network = config.build_network(config.seed);
+
+        SimulationController::new(config, decision_network, 100).unwrap()
+    }
+
+    #[test]
+    fn test_pause_and_single_step() {
+        let mut controller = controller();
+        controller.event(&update());
+        assert_eq!(controller.simulation.tick, 1);
+
+        controller.event(&press(Key::Space));
+        controller.event(&update());
+        controller.event(&update());
+        assert_eq!(controller.simulation.tick, 1);
+
+        controller.event(&press(Key::Right));
+        assert_eq!(controller.simulation.tick, 2);
+
+        controller.event(&press(Key::Space));
+        controller.event(&update());
+        assert_eq!(controller.simulation.tick, 3);
+
+        // Single stepping only works while paused
+        controller.event(&press(Key::Right));
+        assert_eq!(controller.simulation.tick, 3);
+    }
+
+    #[test]
+    fn test_speed_and_reset() {
+        let mut controller = controller();
+        controller.event(&press(Key::Up));
+        assert_eq!(controller.ups, 200);
+        for _ in 0..20 {
+            controller.event(&press(Key::Down));
+        }
+        assert_eq!(controller.ups, MIN_UPS);
+
+        assert_eq!(controller.event(&update()), None);
+        assert_eq!(controller.event(&press(Key::R)), Some("Reset with seed 1".to_string()));
+        assert_eq!(controller.seed, 1);
+        assert_eq!(controller.simulation.tick, 0);
+    }
+
+    #[test]
+    fn test_reset_does_not_reload_the_map() {
+        let path = std::env::temp_dir().join("ant_sim_test_controller_map.txt");
+        std::fs::copy("maps/maze.txt", &path).unwrap();
+        let mut config = SimulationConfig::new();
+        config.environment.map = Some(path.to_str().unwrap().to_string());
+        let decision_network = config.build_network(config.seed);
+        let mut controller = SimulationController::new(config.clone(), decision_network.clone(), 100).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let total_food = controller.simulation.environment.total_food_remaining();
+
+        controller.simulation.run(10);
+        controller.event(&press(Key::R));
+        assert_eq!(controller.simulation.tick, 0);
+        assert_eq!(controller.simulation.environment.total_food_remaining(), total_food);
+        assert!(SimulationController::new(config, decision_network, 100).is_err());
+    }
+
+    #[test]
+    fn test_replay_controls() {
+        let mut simulation = controller().simulation;
+        let recorder = EpisodeRecorder::new(&simulation, None, 0, 4);
+        let recorder = std::sync::Arc::new(std::sync::Mutex::new(recorder));
+        simulation.add_observer(Box::new(recorder.clone()));
+        simulation.run(10);
+        drop(simulation);
+        let replay = std::sync::Arc::try_unwrap(recorder).ok().unwrap().into_inner().unwrap().into_replay();
+        let mut controller = ReplayController::new(ReplayPlayer::new(replay), 100);
+
+        controller.event(&update());
+        controller.event(&press(Key::Space));
+        controller.event(&press(Key::Right));
+        assert_eq!(controller.player.tick(), 2);
+        controller.event(&press(Key::Left));
+        controller.event(&press(Key::Left));
+        assert_eq!(controller.player.tick(), 0);
+        controller.event(&press(Key::RightBracket));
+        assert_eq!(controller.player.tick(), 4);
+
+        // Playing stops at the end of the recording
+        controller.event(&press(Key::Space));
+        for _ in 0..6 {
+            controller.event(&update());
+        }
+        assert_eq!(controller.event(&update()), Some("End of the replay at tick 10".to_string()));
+        assert_eq!(controller.player.tick(), 10);
+        assert!(controller.paused);
+        controller.event(&press(Key::R));
+        assert_eq!(controller.player.tick(), 0);
+    }
+}
@@ -0,0 +1,211 @@
+use piston::input::{Button, GenericEvent, Key, MouseButton};
+
+use crate::simulation::environment::Environment;
+use crate::simulation::map::save_map;
+
+
+// Mouse editing of a running world. The left mouse button applies the current tool to every
+// cell it is dragged over and the right mouse button undoes it:
+//   W       paint walls (right click clears them)
+//   F       drop food (right click removes it)
+//   E       erase pheromone
+//   N       move the nest to the clicked cell
+//   [ / ]   decrease / increase the amount of food dropped
+//   S       save the world to the editor's save path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditTool {
+    Wall,
+    Food,
+    ErasePheromone,
+    Nest,
+}
+
+
+pub struct WorldEditor {
+    pub tool: EditTool,
+    pub food_amount: f64,
+    // Cells within this many cells of the cursor are edited, 0 edits just the one under it
+    pub brush_radius: usize,
+    pub nest_size: usize,
+    pub pixel_size: usize,
+    pub save_path: String,
+    cursor: [f64; 2],
+    held_button: Option<MouseButton>,
+}
+
+
+impl WorldEditor {
+    pub fn new(pixel_size: usize, save_path: String) -> WorldEditor {
+        WorldEditor {
+            tool: EditTool::Wall,
+            food_amount: 1.0,
+            brush_radius: 0,
+            nest_size: 5,
+            pixel_size,
+            save_path,
+            cursor: [0.0; 2],
+            held_button: None,
+        }
+    }
+
+    fn cursor_cell(&self, environment: &Environment) -> Option<[usize; 2]> {
+        if self.cursor[0] < 0.0 || self.cursor[1] < 0.0 {
+            return None
+        }
+        let cell = [
+            (self.cursor[0] / self.pixel_size as f64) as usize,
+            (self.cursor[1] / self.pixel_size as f64) as usize,
+        ];
+        if cell[0] >= environment.size || cell[1] >= environment.size {
+            return None
+        }
+
+        Some(cell)
+    }
+
+    fn apply(&self, environment: &mut Environment, button: MouseButton) {
+        let centre = match self.cursor_cell(environment) {
+            Some(cell) => cell,
+            None => return,
+        };
+        let erase = button == MouseButton::Right;
+        if self.tool == EditTool::Nest {
+            if !erase {
+                environment.move_nest(centre, self.nest_size);
+            }
+            return
+        }
+
+        let start_i = centre[0].saturating_sub(self.brush_radius);
+        let start_j = centre[1].saturating_sub(self.brush_radius);
+        for i in start_i..usize::min(centre[0] + self.brush_radius + 1, environment.size) {
+            for j in start_j..usize::min(centre[1] + self.brush_radius + 1, environment.size) {
+                match self.tool {
+                    EditTool::Wall => environment.set_traversable([i, j], erase),
+                    EditTool::Food => environment.set_food([i, j], if erase { 0.0 } else { self.food_amount }),
+                    EditTool::ErasePheromone => environment.clear_pheromones([i, j]),
+                    EditTool::Nest => {},
+                }
+            }
+        }
+    }
+
+    // Returns a message describing what the key did, if it did anything
+    fn key_pressed(&mut self, key: Key, environment: &Environment) -> Option<String> {
+        match key {
+            Key::W => self.tool = EditTool::Wall,
+            Key::F => self.tool = EditTool::Food,
+            Key::E => self.tool = EditTool::ErasePheromone,
+            Key::N => self.tool = EditTool::Nest,
+            Key::LeftBracket => {
+                self.food_amount = f64::max(self.food_amount - 0.1, 0.1);
+                return Some(format!("Food amount: {:.1}", self.food_amount))
+            },
+            Key::RightBracket => {
+                self.food_amount = f64::min(self.food_amount + 0.1, 1.0);
+                return Some(format!("Food amount: {:.1}", self.food_amount))
+            },
+            Key::S => return match save_map(environment, &self.save_path) {
+                Ok(()) => Some(format!("Saved map to {}", self.save_path)),
+                Err(e) => Some(format!("Could not save the map to {}: {}", self.save_path, e)),
+            },
+            _ => return None,
+        }
+
+        Some(format!("Edit tool: {:?}", self.tool))
+    }
+
+    // Returns a message for the user when a key changed the editor's settings or saved the map
+    pub fn event<E: GenericEvent>(&mut self, e: &E, environment: &mut Environment) -> Option<String> {
+        let mut message = None;
+        if let Some(cursor) = e.mouse_cursor_args() {
+            self.cursor = cursor;
+            if let Some(button) = self.held_button {
+                self.apply(environment, button);
+            }
+        }
+        match e.press_args() {
+            Some(Button::Mouse(button)) if button == MouseButton::Left || button == MouseButton::Right => {
+                self.held_button = Some(button);
+                self.apply(environment, button);
+            },
+            Some(Button::Keyboard(key)) => message = self.key_pressed(key, environment),
+            _ => {},
+        }
+        if let Some(Button::Mouse(button)) = e.release_args() {
+            if self.held_button == Some(button) {
+                self.held_button = None;
+            }
+        }
+
+        message
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piston::input::{ButtonArgs, ButtonState, Event, Input, Motion};
+
+    fn button(state: ButtonState, button: Button) -> Event {
+        Event::Input(Input::Button(ButtonArgs { state, button, scancode: None }), None)
+    }
+
+    fn move_to(cell: [usize; 2]) -> Event {
+        // The middle of the cell at the editor's pixel size of 4
+        Event::Input(Input::Move(Motion::MouseCursor([cell[0] as f64 * 4. + 2., cell[1] as f64 * 4. + 2.])), None)
+    }
+
+    #[test]
+    fn test_drag_paints_walls() {
+        let mut environment = Environment::new(20, 0.99);
+        let mut editor = WorldEditor::new(4, "unused.txt".to_string());
+        editor.event(&move_to([10, 5]), &mut environment);
+        editor.event(&button(ButtonState::Press, Button::Mouse(MouseButton::Left)), &mut environment);
+        for j in 6..10 {
+            editor.event(&move_to([10, j]), &mut environment);
+        }
+        editor.event(&button(ButtonState::Release, Button::Mouse(MouseButton::Left)), &mut environment);
+        editor.event(&move_to([10, 12]), &mut environment);
+
+        for j in 5..10 {
+            assert!(!environment.cell_is_traversable([10, j]));
+        }
+        assert!(environment.cell_is_traversable([10, 12]));
+
+        // Right dragging clears them again, but never the outer wall
+        editor.event(&button(ButtonState::Press, Button::Mouse(MouseButton::Right)), &mut environment);
+        editor.event(&move_to([10, 5]), &mut environment);
+        editor.event(&move_to([10, 0]), &mut environment);
+        assert!(environment.cell_is_traversable([10, 5]));
+        assert!(!environment.cell_is_traversable([10, 0]));
+    }
+
+    #[test]
+    fn test_food_pheromone_and_nest_tools() {
+        let mut environment = Environment::new(20, 0.99);
+        let mut editor = WorldEditor::new(4, "unused.txt".to_string());
+        let click = |editor: &mut WorldEditor, environment: &mut Environment, cell: [usize; 2]| {
+            editor.event(&move_to(cell), environment);
+            editor.event(&button(ButtonState::Press, Button::Mouse(MouseButton::Left)), environment);
+            editor.event(&button(ButtonState::Release, Button::Mouse(MouseButton::Left)), environment);
+        };
+
+        editor.event(&button(ButtonState::Press, Button::Keyboard(Key::F)), &mut environment);
+        editor.event(&button(ButtonState::Press, Button::Keyboard(Key::LeftBracket)), &mut environment);
+        click(&mut editor, &mut environment, [8, 8]);
+        assert!((environment.grid[8][8].food_amount - 0.9).abs() < 1e-9);
+
+        environment.place_food_pheromone([9, 9]);
+        editor.event(&button(ButtonState::Press, Button::Keyboard(Key::E)), &mut environment);
+        click(&mut editor, &mut environment, [9, 9]);
+        assert_eq!(environment.grid[9][9].food_pheromone_concentration, 0.0);
+
+        editor.event(&button(ButtonState::Press, Button::Keyboard(Key::N)), &mut environment);
+        click(&mut editor, &mut environment, [12, 4]);
+        assert!(!environment.cell_is_nest([3, 3]));
+        assert!(environment.cell_is_nest([12, 4]));
+        assert_eq!(environment.nest_location(), Some([12, 4]));
+    }
+}
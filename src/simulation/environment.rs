@@ -1,8 +1,9 @@
 use ndarray::{Array, Dim};
 use piston::input::GenericEvent;
+use serde::{Deserialize, Serialize};
 
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Cell {
     pub coordinates: [usize; 2],
     pub nest_pheromone_concentration: f64,
@@ -14,11 +15,40 @@ pub struct Cell {
 }
 
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Stencil {
+    FourNeighbour,
+    EightNeighbour,
+}
+
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PheromoneSettings {
+    // Fraction of the pheromone in a cell that disappears every tick, in [0, 1]
+    pub evaporation_rate: f64,
+    // Fraction of the pheromone in a cell that spreads to its neighbours every tick, in [0, 1]
+    pub diffusion_coefficient: f64,
+}
+
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiffusionSettings {
+    pub nest_pheromone: PheromoneSettings,
+    pub food_pheromone: PheromoneSettings,
+    pub stencil: Stencil,
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Environment {
-    pub diffusion_rate: f64,
+    pub diffusion_settings: DiffusionSettings,
     pub size: usize,
     pub grid: Vec<Vec<Cell>>,
     pub food_returned_to_nest: f64,
+    // Total pheromone concentration ants have added to the grid
+    pub pheromone_deposited: f64,
 }
 
 
@@ -47,36 +77,122 @@ impl Cell {
         Array::from(vec![self.coordinates[0] as f32, self.coordinates[1] as f32])
     }
 
-    fn update(&mut self, diffusion_rate: f64) {
-        if self.nest_pheromone_concentration < 0.01 {
-            self.nest_pheromone_concentration = 0.0;
+    // Pheromone decays geometrically and is never cut off to zero, so faint trails keep
+    // diffusing instead of vanishing below a threshold
+    fn evaporate(&mut self, diffusion_settings: &DiffusionSettings) {
+        self.nest_pheromone_concentration *= 1.0 - diffusion_settings.nest_pheromone.evaporation_rate;
+        self.food_pheromone_concentration *= 1.0 - diffusion_settings.food_pheromone.evaporation_rate;
+    }
+}
+
+
+impl Stencil {
+    fn offsets(&self) -> &'static [[i32; 2]] {
+        match self {
+            Stencil::FourNeighbour => &[[-1, 0], [1, 0], [0, -1], [0, 1]],
+            Stencil::EightNeighbour => &[
+                [-1, -1], [-1, 0], [-1, 1],
+                [0, -1], [0, 1],
+                [1, -1], [1, 0], [1, 1],
+            ],
+        }
+    }
+}
+
+
+impl PheromoneSettings {
+    // Values from config files are checked by DiffusionSettings::validate
+    pub fn new(evaporation_rate: f64, diffusion_coefficient: f64) -> PheromoneSettings {
+        PheromoneSettings {
+            evaporation_rate,
+            diffusion_coefficient,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.evaporation_rate) {
+            return Err(format!("evaporation_rate must be in [0, 1], got {}", self.evaporation_rate))
         }
-        if self.food_pheromone_concentration < 0.01 {
-            self.food_pheromone_concentration = 0.0;
+        if !(0.0..=1.0).contains(&self.diffusion_coefficient) {
+            return Err(format!("diffusion_coefficient must be in [0, 1], got {}", self.diffusion_coefficient))
         }
-        self.nest_pheromone_concentration *= diffusion_rate;
-        self.food_pheromone_concentration *= diffusion_rate;
+
+        Ok(())
+    }
+}
+
+
+impl DiffusionSettings {
+    pub fn new() -> DiffusionSettings {
+        DiffusionSettings {
+            nest_pheromone: PheromoneSettings::new(0.001, 0.1),
+            food_pheromone: PheromoneSettings::new(0.001, 0.1),
+            stencil: Stencil::FourNeighbour,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let pheromones = [("nest_pheromone", &self.nest_pheromone), ("food_pheromone", &self.food_pheromone)];
+        for (name, pheromone) in pheromones.iter() {
+            if let Err(message) = pheromone.validate() {
+                return Err(format!("{}.{}", name, message))
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn from_diffusion_rate(diffusion_rate: f64) -> DiffusionSettings {
+        // The old single "diffusion rate" was the fraction of pheromone kept every tick
+        assert!((0.0..=1.0).contains(&diffusion_rate), "diffusion_rate must be in [0, 1], got {}", diffusion_rate);
+        let mut diffusion_settings = DiffusionSettings::new();
+        diffusion_settings.nest_pheromone.evaporation_rate = 1.0 - diffusion_rate;
+        diffusion_settings.food_pheromone.evaporation_rate = 1.0 - diffusion_rate;
+
+        diffusion_settings
+    }
+}
+
+
+impl Default for DiffusionSettings {
+    fn default() -> DiffusionSettings {
+        DiffusionSettings::new()
     }
 }
 
 
 impl Environment {
     pub fn new(arena_size: usize, diffusion_rate: f64) -> Environment {
-        let mut grid = vec![vec![Cell::new([0, 0]); arena_size]; arena_size];
+        Environment::with_diffusion_settings(arena_size, DiffusionSettings::from_diffusion_rate(diffusion_rate))
+    }
+
+    pub fn with_diffusion_settings(arena_size: usize, diffusion_settings: DiffusionSettings) -> Environment {
+        let grid = vec![vec![Cell::new([0, 0]); arena_size]; arena_size];
+        let mut environment = Environment::from_grid(grid, diffusion_settings);
+        environment.set_nest_area();
+        environment.place_food();
+
+        environment
+    }
+
+    pub fn from_grid(mut grid: Vec<Vec<Cell>>, diffusion_settings: DiffusionSettings) -> Environment {
+        // The grid is indexed grid[x][y] and must be square. The outermost cells are always
+        // made impassable so that ants can never walk off the edge of the arena.
+        let arena_size = grid.len();
+        assert!(grid.iter().all(|column| column.len() == arena_size), "grid must be square");
         for i in 0..arena_size {
             for j in 0..arena_size {
                 grid[i][j].coordinates = [i, j];
             }
         }
         let mut environment = Environment {
-            diffusion_rate: diffusion_rate,
+            diffusion_settings,
             size: arena_size,
             grid: grid,
-            food_returned_to_nest: 0.
+            food_returned_to_nest: 0.,
+            pheromone_deposited: 0.,
         };
         environment.pad_edges();
-        environment.set_nest_area();
-        environment.place_food();
 
         environment
     }
@@ -107,19 +223,70 @@ impl Environment {
     }
 
     pub fn place_nest_pheromone(&mut self, index: [usize; 2]) {
+        let before = self.grid[index[0]][index[1]].nest_pheromone_concentration;
         if self.grid[index[0]][index[1]].nest_pheromone_concentration > 0.9 {
             self.grid[index[0]][index[1]].nest_pheromone_concentration = 1.0;
         } else {
             self.grid[index[0]][index[1]].nest_pheromone_concentration += 0.01;
         }
+        self.pheromone_deposited += f64::max(self.grid[index[0]][index[1]].nest_pheromone_concentration - before, 0.0);
     }
 
     pub fn place_food_pheromone(&mut self, index: [usize; 2]) {
+        let before = self.grid[index[0]][index[1]].food_pheromone_concentration;
         if self.grid[index[0]][index[1]].food_pheromone_concentration > 0.9 {
             self.grid[index[0]][index[1]].food_pheromone_concentration = 1.0;
         } else {
             self.grid[index[0]][index[1]].food_pheromone_concentration += 0.01;
         }
+        self.pheromone_deposited += f64::max(self.grid[index[0]][index[1]].food_pheromone_concentration - before, 0.0);
+    }
+
+    pub fn is_edge(&self, index: [usize; 2]) -> bool {
+        index[0] == 0 || index[1] == 0 || index[0] >= self.size - 1 || index[1] >= self.size - 1
+    }
+
+    pub fn set_traversable(&mut self, index: [usize; 2], is_traversable: bool) {
+        // The outer wall is what stops ants walking off the grid, so it can never be removed
+        if self.is_edge(index) {
+            return
+        }
+        let cell = &mut self.grid[index[0]][index[1]];
+        cell.is_traversable = is_traversable;
+        if !is_traversable {
+            cell.food_amount = 0.0;
+            cell.nest_pheromone_concentration = 0.0;
+            cell.food_pheromone_concentration = 0.0;
+        }
+    }
+
+    pub fn set_food(&mut self, index: [usize; 2], food_amount: f64) {
+        if self.cell_is_traversable(index) && !self.cell_is_nest(index) {
+            self.grid[index[0]][index[1]].food_amount = food_amount;
+        }
+    }
+
+    pub fn clear_pheromones(&mut self, index: [usize; 2]) {
+        self.grid[index[0]][index[1]].nest_pheromone_concentration = 0.0;
+        self.grid[index[0]][index[1]].food_pheromone_concentration = 0.0;
+    }
+
+    pub fn move_nest(&mut self, centre: [usize; 2], nest_size: usize) {
+        // Replaces the whole nest with a square of nest_size cells around centre, skipping
+        // any walls it overlaps
+        for cell in self.grid.iter_mut().flatten() {
+            cell.is_nest = false;
+        }
+        let start_i = centre[0].saturating_sub(nest_size / 2);
+        let start_j = centre[1].saturating_sub(nest_size / 2);
+        for i in start_i..usize::min(start_i + nest_size, self.size) {
+            for j in start_j..usize::min(start_j + nest_size, self.size) {
+                if self.grid[i][j].is_traversable {
+                    self.grid[i][j].is_nest = true;
+                    self.grid[i][j].food_amount = 0.0;
+                }
+            }
+        }
     }
 
     pub fn cell_has_food(&self, index: [usize; 2]) -> bool {
@@ -147,6 +314,25 @@ impl Environment {
         false
     }
 
+    pub fn nest_location(&self) -> Option<[usize; 2]> {
+        // The nest cell closest to the middle of the nest, which is where new ants start out.
+        // Nest cells that are walls, such as ones on the arena's edge, don't count.
+        let nest_cells: Vec<[usize; 2]> = self.grid
+            .iter()
+            .flatten()
+            .filter(|cell| cell.is_nest && cell.is_traversable)
+            .map(|cell| cell.coordinates)
+            .collect();
+        if nest_cells.is_empty() {
+            return None
+        }
+        let centre_x = nest_cells.iter().map(|c| c[0] as f64).sum::<f64>() / nest_cells.len() as f64;
+        let centre_y = nest_cells.iter().map(|c| c[1] as f64).sum::<f64>() / nest_cells.len() as f64;
+        let distance = |c: &[usize; 2]| (c[0] as f64 - centre_x).powi(2) + (c[1] as f64 - centre_y).powi(2);
+
+        nest_cells.into_iter().min_by(|c1, c2| distance(c1).partial_cmp(&distance(c2)).unwrap())
+    }
+
     pub fn take_food(&mut self, index: [usize; 2]) {
         self.grid[index[0]][index[1]].food_amount -= 0.1;
     }
@@ -198,10 +384,66 @@ impl Environment {
         self.grid[index[0]][index[1]].visited = true;
     }
 
+    pub fn total_nest_pheromone(&self) -> f64 {
+        self.grid.iter().flatten().map(|cell| cell.nest_pheromone_concentration).sum()
+    }
+
+    pub fn total_food_pheromone(&self) -> f64 {
+        self.grid.iter().flatten().map(|cell| cell.food_pheromone_concentration).sum()
+    }
+
+    fn diffuse(&self, concentrations: &[Vec<f64>], diffusion_coefficient: f64) -> Vec<Vec<f64>> {
+        // Every pair of neighbouring traversable cells exchanges the same amount in opposite
+        // directions, so the total amount of pheromone is conserved by this step. Diagonal
+        // neighbours only exchange through a gap, not between two walls that touch at a corner,
+        // the same as generators::reachable_from_nest.
+        let offsets = self.diffusion_settings.stencil.offsets();
+        let share = diffusion_coefficient / offsets.len() as f64;
+        let mut diffused = concentrations.to_vec();
+        for i in 0..self.size {
+            for j in 0..self.size {
+                if !self.grid[i][j].is_traversable {
+                    continue;
+                }
+                for offset in offsets.iter() {
+                    let n_i = i as i32 + offset[0];
+                    let n_j = j as i32 + offset[1];
+                    if n_i < 0 || n_i >= self.size as i32 || n_j < 0 || n_j >= self.size as i32 {
+                        continue;
+                    }
+                    let (n_i, n_j) = (n_i as usize, n_j as usize);
+                    if !self.grid[n_i][n_j].is_traversable {
+                        continue;
+                    }
+                    if offset[0] != 0 && offset[1] != 0 && !self.grid[n_i][j].is_traversable && !self.grid[i][n_j].is_traversable {
+                        continue;
+                    }
+                    diffused[i][j] += share * (concentrations[n_i][n_j] - concentrations[i][j]);
+                }
+            }
+        }
+
+        diffused
+    }
+
     pub fn update(&mut self) {
-        for grid_row in self.grid.iter_mut() {
-            for cell in grid_row.iter_mut() {
-                cell.update(self.diffusion_rate);
+        let nest_concentrations: Vec<Vec<f64>> = self.grid
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.nest_pheromone_concentration).collect())
+            .collect();
+        let food_concentrations: Vec<Vec<f64>> = self.grid
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.food_pheromone_concentration).collect())
+            .collect();
+        let nest_concentrations = self.diffuse(&nest_concentrations, self.diffusion_settings.nest_pheromone.diffusion_coefficient);
+        let food_concentrations = self.diffuse(&food_concentrations, self.diffusion_settings.food_pheromone.diffusion_coefficient);
+
+        let diffusion_settings = self.diffusion_settings;
+        for (i, grid_row) in self.grid.iter_mut().enumerate() {
+            for (j, cell) in grid_row.iter_mut().enumerate() {
+                cell.nest_pheromone_concentration = nest_concentrations[i][j];
+                cell.food_pheromone_concentration = food_concentrations[i][j];
+                cell.evaporate(&diffusion_settings);
             }
         }
     }
@@ -221,4 +463,115 @@ mod tests {
         let environment = Environment::new(100, 0.9);
         assert_eq!(environment.grid[1][1].coordinates, [1, 1]);
     }
+
+    fn environment_with_pheromone(stencil: Stencil, evaporation_rate: f64) -> Environment {
+        let mut diffusion_settings = DiffusionSettings::new();
+        diffusion_settings.stencil = stencil;
+        diffusion_settings.nest_pheromone = PheromoneSettings::new(evaporation_rate, 0.5);
+        diffusion_settings.food_pheromone = PheromoneSettings::new(evaporation_rate, 0.25);
+        let mut environment = Environment::with_diffusion_settings(20, diffusion_settings);
+        environment.grid[10][10].nest_pheromone_concentration = 1.0;
+        environment.grid[1][1].food_pheromone_concentration = 1.0;
+        environment.grid[5][12].food_pheromone_concentration = 0.3;
+
+        environment
+    }
+
+    #[test]
+    fn test_diffusion_conserves_pheromone() {
+        for stencil in [Stencil::FourNeighbour, Stencil::EightNeighbour].iter() {
+            let mut environment = environment_with_pheromone(*stencil, 0.0);
+            for _ in 0..50 {
+                environment.update();
+                assert!((environment.total_nest_pheromone() - 1.0).abs() < 1e-9);
+                assert!((environment.total_food_pheromone() - 1.3).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_diffusion_loses_pheromone_only_through_evaporation() {
+        for stencil in [Stencil::FourNeighbour, Stencil::EightNeighbour].iter() {
+            let mut environment = environment_with_pheromone(*stencil, 0.1);
+            let mut expected_nest = environment.total_nest_pheromone();
+            let mut expected_food = environment.total_food_pheromone();
+            for _ in 0..50 {
+                environment.update();
+                expected_nest *= 0.9;
+                expected_food *= 0.9;
+                assert!((environment.total_nest_pheromone() - expected_nest).abs() < 1e-9);
+                assert!((environment.total_food_pheromone() - expected_food).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_diffusion_spreads_to_neighbours() {
+        let mut environment = environment_with_pheromone(Stencil::FourNeighbour, 0.0);
+        environment.update();
+        assert!(environment.grid[11][10].nest_pheromone_concentration > 0.0);
+        assert_eq!(environment.grid[11][11].nest_pheromone_concentration, 0.0);
+
+        let mut environment = environment_with_pheromone(Stencil::EightNeighbour, 0.0);
+        environment.update();
+        assert!(environment.grid[11][11].nest_pheromone_concentration > 0.0);
+    }
+
+    #[test]
+    fn test_impassable_cells_block_diffusion() {
+        let mut environment = environment_with_pheromone(Stencil::EightNeighbour, 0.0);
+        // Wall off column 12 so nothing can reach the right hand side of the arena
+        for i in 0..environment.size {
+            environment.grid[i][12].is_traversable = false;
+        }
+        environment.grid[5][12].food_pheromone_concentration = 0.0;
+        for _ in 0..100 {
+            environment.update();
+        }
+        for i in 0..environment.size {
+            for j in 12..environment.size {
+                assert_eq!(environment.grid[i][j].nest_pheromone_concentration, 0.0);
+                assert_eq!(environment.grid[i][j].food_pheromone_concentration, 0.0);
+            }
+        }
+        for i in 0..environment.size {
+            assert_eq!(environment.grid[i][0].nest_pheromone_concentration, 0.0);
+        }
+        assert!((environment.total_nest_pheromone() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_no_diffusion_through_wall_corners() {
+        let mut environment = environment_with_pheromone(Stencil::EightNeighbour, 0.0);
+        // A diagonal wall from (1, 18) to (18, 1) that touches only at corners
+        for i in 1..environment.size - 1 {
+            environment.grid[i][environment.size - 1 - i].is_traversable = false;
+        }
+        environment.grid[5][12].food_pheromone_concentration = 0.0;
+        for _ in 0..100 {
+            environment.update();
+        }
+        // The nest pheromone starts at (10, 10), on the far side of the wall from (1, 1)
+        for i in 0..environment.size {
+            for j in 0..environment.size - 1 - i {
+                assert_eq!(environment.grid[i][j].nest_pheromone_concentration, 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pheromone_settings_validate() {
+        assert!(PheromoneSettings::new(0.01, 0.1).validate().is_ok());
+        assert!(PheromoneSettings::new(1.5, 0.1).validate().is_err());
+        let mut diffusion_settings = DiffusionSettings::new();
+        diffusion_settings.food_pheromone.diffusion_coefficient = -0.1;
+        assert!(diffusion_settings.validate().unwrap_err().starts_with("food_pheromone.diffusion_coefficient"));
+        assert!(DiffusionSettings::from_diffusion_rate(0.99).validate().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "diffusion_rate must be in [0, 1]")]
+    fn test_diffusion_rate_out_of_range() {
+        Environment::new(20, 1.5);
+    }
 }
@@ -0,0 +1,471 @@
+use std::collections::VecDeque;
+
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::simulation::environment::{Cell, DiffusionSettings, Environment};
+use crate::simulation::utils::SimulationRng;
+
+
+#[derive(Clone, Copy, Debug)]
+pub enum Layout {
+    // An empty arena, the same as Environment::new but with randomized food
+    Open,
+    // Scattered rocks grown with a cellular automaton from random noise
+    Rocks { fill_probability: f64, smoothing_steps: usize },
+    // A perfect maze carved with a recursive backtracker
+    Maze { corridor_width: usize },
+    // Solid rock with straight corridors dug between random waypoints
+    Corridors { num_waypoints: usize, corridor_width: usize },
+}
+
+
+pub struct ArenaGenerator {
+    pub arena_size: usize,
+    pub layout: Layout,
+    pub nest_size: usize,
+    pub num_food_patches: usize,
+    pub min_food_patch_size: usize,
+    pub max_food_patch_size: usize,
+    // Minimum straight line distance between the nest and the centre of a food patch
+    pub min_food_distance: f64,
+    pub diffusion_settings: DiffusionSettings,
+}
+
+
+impl ArenaGenerator {
+    pub fn new(arena_size: usize, layout: Layout) -> ArenaGenerator {
+        ArenaGenerator {
+            arena_size,
+            layout,
+            nest_size: 5,
+            num_food_patches: 3,
+            min_food_patch_size: 2,
+            max_food_patch_size: 5,
+            min_food_distance: arena_size as f64 / 3.,
+            diffusion_settings: DiffusionSettings::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.nest_size == 0 {
+            return Err("nest_size must be at least 1".to_string())
+        }
+        // The nest and a cell of floor beside it, inside the outer wall
+        if self.arena_size < self.nest_size + 3 {
+            return Err(format!(
+                "arena_size must be at least nest_size + 3 = {}, got {}", self.nest_size + 3, self.arena_size
+            ))
+        }
+        if self.min_food_patch_size == 0 || self.min_food_patch_size > self.max_food_patch_size {
+            return Err(format!(
+                "food patch sizes must satisfy 1 <= min_food_patch_size <= max_food_patch_size, got {} and {}",
+                self.min_food_patch_size, self.max_food_patch_size
+            ))
+        }
+        if self.min_food_distance.is_nan() || self.min_food_distance < 0.0 {
+            return Err(format!("min_food_distance must be at least 0, got {}", self.min_food_distance))
+        }
+        if let Layout::Rocks { fill_probability, .. } = self.layout {
+            if !(0.0..=1.0).contains(&fill_probability) {
+                return Err(format!("fill_probability must be in [0, 1], got {}", fill_probability))
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn generate(&self, seed: u64) -> Result<Environment, String> {
+        self.validate()?;
+        let mut rng = SimulationRng::seed_from_u64(seed);
+        let mut traversable = match self.layout {
+            Layout::Open => vec![vec![true; self.arena_size]; self.arena_size],
+            Layout::Rocks { fill_probability, smoothing_steps } => {
+                rocks(self.arena_size, fill_probability, smoothing_steps, &mut rng)
+            },
+            Layout::Maze { corridor_width } => maze(self.arena_size, corridor_width, &mut rng),
+            Layout::Corridors { num_waypoints, corridor_width } => {
+                corridors(self.arena_size, num_waypoints, corridor_width, self.nest_size, &mut rng)
+            },
+        };
+        // The nest always sits in the same corner as in Environment::new, clear of obstacles
+        let nest_end = usize::min(1 + self.nest_size, self.arena_size - 1);
+        for i in 1..nest_end {
+            for j in 1..nest_end {
+                traversable[i][j] = true;
+            }
+        }
+        connect_regions(&mut traversable, [1, 1]);
+
+        let mut grid = vec![vec![Cell::new([0, 0]); self.arena_size]; self.arena_size];
+        for i in 0..self.arena_size {
+            for j in 0..self.arena_size {
+                grid[i][j].is_traversable = traversable[i][j];
+                grid[i][j].is_nest = i >= 1 && i < nest_end && j >= 1 && j < nest_end;
+            }
+        }
+        let mut environment = Environment::from_grid(grid, self.diffusion_settings);
+        self.place_food_patches(&mut environment, &mut rng);
+
+        Ok(environment)
+    }
+
+    fn place_food_patches(&self, environment: &mut Environment, rng: &mut SimulationRng) {
+        // Food is only ever placed on cells that can be reached from the nest, so every patch
+        // is guaranteed to be reachable no matter how the obstacles turned out
+        let reachable = reachable_from_nest(environment);
+        let nest_location = environment.nest_location().unwrap();
+        let distance_to_nest = |cell: &[usize; 2]| {
+            ((cell[0] as f64 - nest_location[0] as f64).powi(2) + (cell[1] as f64 - nest_location[1] as f64).powi(2)).sqrt()
+        };
+        let mut candidates: Vec<[usize; 2]> = Vec::new();
+        for i in 0..environment.size {
+            for j in 0..environment.size {
+                if reachable[i][j] && !environment.grid[i][j].is_nest {
+                    candidates.push([i, j]);
+                }
+            }
+        }
+        let far_candidates: Vec<[usize; 2]> = candidates
+            .iter()
+            .cloned()
+            .filter(|cell| distance_to_nest(cell) >= self.min_food_distance)
+            .collect();
+        // Small or cramped arenas might not have anything far enough away, so fall back to
+        // anywhere that is reachable
+        let candidates = if far_candidates.is_empty() { candidates } else { far_candidates };
+        if candidates.is_empty() {
+            return
+        }
+
+        for _ in 0..self.num_food_patches {
+            let centre = *candidates.choose(rng).unwrap();
+            let patch_size = rng.gen_range(self.min_food_patch_size..=self.max_food_patch_size);
+            let start_i = centre[0].saturating_sub(patch_size / 2);
+            let start_j = centre[1].saturating_sub(patch_size / 2);
+            for i in start_i..usize::min(start_i + patch_size, environment.size) {
+                for j in start_j..usize::min(start_j + patch_size, environment.size) {
+                    if reachable[i][j] && !environment.grid[i][j].is_nest {
+                        environment.grid[i][j].food_amount = 1.0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+fn rocks(arena_size: usize, fill_probability: f64, smoothing_steps: usize, rng: &mut SimulationRng) -> Vec<Vec<bool>> {
+    let mut is_rock: Vec<Vec<bool>> = (0..arena_size)
+        .map(|_| (0..arena_size).map(|_| rng.gen_bool(fill_probability)).collect())
+        .collect();
+
+    // The usual 4-5 rule: a cell turns to rock when most of its neighbours are rock and
+    // clears when few of them are, which clumps the noise into rounded boulders
+    for _ in 0..smoothing_steps {
+        let mut smoothed = is_rock.clone();
+        for i in 0..arena_size {
+            for j in 0..arena_size {
+                let mut rock_neighbours = 0;
+                for d_i in -1..=1 {
+                    for d_j in -1..=1 {
+                        if d_i == 0 && d_j == 0 {
+                            continue;
+                        }
+                        let n_i = i as i32 + d_i;
+                        let n_j = j as i32 + d_j;
+                        // Out of bounds counts as rock so boulders can grow off the walls
+                        if n_i < 0 || n_i >= arena_size as i32 || n_j < 0 || n_j >= arena_size as i32
+                            || is_rock[n_i as usize][n_j as usize] {
+                            rock_neighbours += 1;
+                        }
+                    }
+                }
+                if rock_neighbours >= 5 {
+                    smoothed[i][j] = true;
+                } else if rock_neighbours <= 3 {
+                    smoothed[i][j] = false;
+                }
+            }
+        }
+        is_rock = smoothed;
+    }
+
+    is_rock.iter().map(|row| row.iter().map(|rock| !rock).collect()).collect()
+}
+
+
+fn maze(arena_size: usize, corridor_width: usize, rng: &mut SimulationRng) -> Vec<Vec<bool>> {
+    // The arena is divided into maze cells of corridor_width floor cells, separated by walls
+    // one cell thick. Maze cell (a, b) starts at grid cell 1 + a * (corridor_width + 1).
+    let corridor_width = usize::max(corridor_width, 1);
+    let pitch = corridor_width + 1;
+    let num_maze_cells = usize::max((arena_size - 1) / pitch, 1);
+    let mut traversable = vec![vec![false; arena_size]; arena_size];
+    let carve = |traversable: &mut Vec<Vec<bool>>, start: [usize; 2], end: [usize; 2]| {
+        for i in start[0]..usize::min(end[0], arena_size - 1) {
+            for j in start[1]..usize::min(end[1], arena_size - 1) {
+                traversable[i][j] = true;
+            }
+        }
+    };
+
+    let mut visited = vec![vec![false; num_maze_cells]; num_maze_cells];
+    let mut stack: Vec<[usize; 2]> = vec![[0, 0]];
+    visited[0][0] = true;
+    carve(&mut traversable, [1, 1], [1 + corridor_width, 1 + corridor_width]);
+    while let Some(&current) = stack.last() {
+        let mut neighbours: Vec<[usize; 2]> = Vec::new();
+        if current[0] > 0 {
+            neighbours.push([current[0] - 1, current[1]]);
+        }
+        if current[0] + 1 < num_maze_cells {
+            neighbours.push([current[0] + 1, current[1]]);
+        }
+        if current[1] > 0 {
+            neighbours.push([current[0], current[1] - 1]);
+        }
+        if current[1] + 1 < num_maze_cells {
+            neighbours.push([current[0], current[1] + 1]);
+        }
+        neighbours.retain(|n| !visited[n[0]][n[1]]);
+
+        match neighbours.choose(rng) {
+            Some(&next) => {
+                visited[next[0]][next[1]] = true;
+                // Carve the next maze cell and the wall between it and the current one
+                let low = [usize::min(current[0], next[0]), usize::min(current[1], next[1])];
+                let high = [usize::max(current[0], next[0]), usize::max(current[1], next[1])];
+                carve(
+                    &mut traversable,
+                    [1 + low[0] * pitch, 1 + low[1] * pitch],
+                    [1 + high[0] * pitch + corridor_width, 1 + high[1] * pitch + corridor_width],
+                );
+                stack.push(next);
+            },
+            None => {
+                stack.pop();
+            },
+        }
+    }
+
+    traversable
+}
+
+
+fn corridors(
+    arena_size: usize,
+    num_waypoints: usize,
+    corridor_width: usize,
+    nest_size: usize,
+    rng: &mut SimulationRng
+) -> Vec<Vec<bool>> {
+    // Every new waypoint is joined to one that is already connected, starting from the nest,
+    // so the corridors always form a single connected network
+    let corridor_width = usize::max(corridor_width, 1);
+    let mut traversable = vec![vec![false; arena_size]; arena_size];
+    let max_coordinate = usize::max(arena_size.saturating_sub(1 + corridor_width), 2);
+    let mut waypoints: Vec<[usize; 2]> = vec![[1 + nest_size / 2, 1 + nest_size / 2]];
+    let dig = |traversable: &mut Vec<Vec<bool>>, from: usize, to: usize, fixed: usize, horizontal: bool| {
+        for k in usize::min(from, to)..=usize::max(from, to) {
+            for w in 0..corridor_width {
+                let (i, j) = if horizontal { (k, fixed + w) } else { (fixed + w, k) };
+                if i < arena_size - 1 && j < arena_size - 1 {
+                    traversable[i][j] = true;
+                }
+            }
+        }
+    };
+
+    for _ in 0..num_waypoints {
+        let waypoint = [rng.gen_range(1..max_coordinate), rng.gen_range(1..max_coordinate)];
+        let connect_to = *waypoints.choose(rng).unwrap();
+        // An L shaped corridor, going along x first and then along y
+        dig(&mut traversable, connect_to[0], waypoint[0], connect_to[1], true);
+        dig(&mut traversable, connect_to[1], waypoint[1], waypoint[0], false);
+        waypoints.push(waypoint);
+    }
+
+    traversable
+}
+
+
+fn flood_fill(traversable: &[Vec<bool>], start: [usize; 2], labels: &mut Vec<Vec<usize>>, label: usize) -> Vec<[usize; 2]> {
+    // Marks every open cell 4-connected to start with the given label, skipping the outer ring
+    // of cells since Environment::from_grid always turns that into walls
+    let arena_size = traversable.len();
+    let mut region: Vec<[usize; 2]> = vec![start];
+    let mut queue: VecDeque<[usize; 2]> = VecDeque::new();
+    labels[start[0]][start[1]] = label;
+    queue.push_back(start);
+    while let Some(current) = queue.pop_front() {
+        let neighbours = [
+            [current[0] - 1, current[1]],
+            [current[0] + 1, current[1]],
+            [current[0], current[1] - 1],
+            [current[0], current[1] + 1],
+        ];
+        for neighbour in neighbours.iter() {
+            if neighbour[0] < 1 || neighbour[0] >= arena_size - 1 || neighbour[1] < 1 || neighbour[1] >= arena_size - 1 {
+                continue;
+            }
+            if traversable[neighbour[0]][neighbour[1]] && labels[neighbour[0]][neighbour[1]] == 0 {
+                labels[neighbour[0]][neighbour[1]] = label;
+                region.push(*neighbour);
+                queue.push_back(*neighbour);
+            }
+        }
+    }
+
+    region
+}
+
+
+fn connect_regions(traversable: &mut Vec<Vec<bool>>, nest_location: [usize; 2]) {
+    // Noise based layouts can leave open pockets cut off from the nest. Pockets too small to be
+    // worth exploring are filled in, and the rest get an L shaped tunnel dug back to the nest.
+    const MIN_REGION_SIZE: usize = 10;
+    let arena_size = traversable.len();
+    let mut labels = vec![vec![0; arena_size]; arena_size];
+    flood_fill(traversable, nest_location, &mut labels, 1);
+    let mut next_label = 2;
+    for i in 1..arena_size - 1 {
+        for j in 1..arena_size - 1 {
+            if !traversable[i][j] || labels[i][j] != 0 {
+                continue;
+            }
+            let region = flood_fill(traversable, [i, j], &mut labels, next_label);
+            next_label += 1;
+            if region.len() < MIN_REGION_SIZE {
+                for cell in region.iter() {
+                    traversable[cell[0]][cell[1]] = false;
+                }
+            } else {
+                for x in usize::min(i, nest_location[0])..=usize::max(i, nest_location[0]) {
+                    traversable[x][j] = true;
+                }
+                for y in usize::min(j, nest_location[1])..=usize::max(j, nest_location[1]) {
+                    traversable[nest_location[0]][y] = true;
+                }
+            }
+        }
+    }
+}
+
+
+pub fn reachable_from_nest(environment: &Environment) -> Vec<Vec<bool>> {
+    // Flood fill over traversable cells from every nest cell. Only the four direct neighbours
+    // count, since ants can't reliably squeeze between two diagonally touching walls.
+    let mut reachable = vec![vec![false; environment.size]; environment.size];
+    let mut queue: VecDeque<[usize; 2]> = VecDeque::new();
+    for cell in environment.grid.iter().flatten() {
+        if cell.is_nest && cell.is_traversable {
+            reachable[cell.coordinates[0]][cell.coordinates[1]] = true;
+            queue.push_back(cell.coordinates);
+        }
+    }
+
+    while let Some(current) = queue.pop_front() {
+        let neighbours = [
+            [current[0] as i32 - 1, current[1] as i32],
+            [current[0] as i32 + 1, current[1] as i32],
+            [current[0] as i32, current[1] as i32 - 1],
+            [current[0] as i32, current[1] as i32 + 1],
+        ];
+        for neighbour in neighbours.iter() {
+            if neighbour[0] < 0 || neighbour[0] >= environment.size as i32
+                || neighbour[1] < 0 || neighbour[1] >= environment.size as i32 {
+                continue;
+            }
+            let neighbour = [neighbour[0] as usize, neighbour[1] as usize];
+            if !reachable[neighbour[0]][neighbour[1]] && environment.cell_is_traversable(neighbour) {
+                reachable[neighbour[0]][neighbour[1]] = true;
+                queue.push_back(neighbour);
+            }
+        }
+    }
+
+    reachable
+}
+
+
+pub fn all_food_reachable(environment: &Environment) -> bool {
+    let reachable = reachable_from_nest(environment);
+
+    environment.grid
+        .iter()
+        .flatten()
+        .filter(|cell| cell.food_amount > 0.0)
+        .all(|cell| reachable[cell.coordinates[0]][cell.coordinates[1]])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layouts() -> Vec<Layout> {
+        vec![
+            Layout::Open,
+            Layout::Rocks { fill_probability: 0.45, smoothing_steps: 4 },
+            Layout::Maze { corridor_width: 3 },
+            Layout::Corridors { num_waypoints: 8, corridor_width: 2 },
+        ]
+    }
+
+    #[test]
+    fn test_generated_food_is_reachable() {
+        for layout in layouts() {
+            let generator = ArenaGenerator::new(60, layout);
+            for seed in 0..10 {
+                let environment = generator.generate(seed).unwrap();
+                assert!(environment.total_food_remaining() > 0.0);
+                assert!(all_food_reachable(&environment));
+                assert!(environment.cell_is_nest([1, 1]));
+                assert!(!environment.cell_is_traversable([0, 0]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        for layout in layouts() {
+            let generator = ArenaGenerator::new(40, layout);
+            let environment_1 = generator.generate(7).unwrap();
+            let environment_2 = generator.generate(7).unwrap();
+            for (row_1, row_2) in environment_1.grid.iter().zip(environment_2.grid.iter()) {
+                for (cell_1, cell_2) in row_1.iter().zip(row_2.iter()) {
+                    assert_eq!(cell_1.is_traversable, cell_2.is_traversable);
+                    assert_eq!(cell_1.food_amount, cell_2.food_amount);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_parameters_are_errors() {
+        assert!(ArenaGenerator::new(0, Layout::Open).generate(0).is_err());
+        assert!(ArenaGenerator::new(7, Layout::Maze { corridor_width: 1 }).generate(0).is_err());
+        assert!(ArenaGenerator::new(8, Layout::Open).generate(0).is_ok());
+        let mut generator = ArenaGenerator::new(30, Layout::Open);
+        generator.min_food_patch_size = 6;
+        assert!(generator.generate(0).is_err());
+        let generator = ArenaGenerator::new(30, Layout::Rocks { fill_probability: 1.5, smoothing_steps: 2 });
+        assert!(generator.generate(0).is_err());
+    }
+
+    #[test]
+    fn test_all_food_reachable_detects_walled_off_food() {
+        let mut environment = ArenaGenerator::new(30, Layout::Open).generate(0).unwrap();
+        environment.grid[20][20].food_amount = 1.0;
+        assert!(all_food_reachable(&environment));
+        for i in 19..22 {
+            for j in 19..22 {
+                if [i, j] != [20, 20] {
+                    environment.grid[i][j].is_traversable = false;
+                }
+            }
+        }
+        assert!(!all_food_reachable(&environment));
+    }
+}
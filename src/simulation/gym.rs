@@ -0,0 +1,203 @@
+use std::fmt;
+
+use ndarray::{Array, Dim};
+
+use crate::config::SimulationConfig;
+use crate::simulation::colony::AntEvent;
+use crate::simulation::environment::Environment;
+use crate::simulation::map::MapError;
+use crate::simulation::simulation::{Simulation, SimulationResult};
+use crate::training::reward::Reward;
+
+
+// One row per ant, laid out as Ant::get_feature_vector builds them for the decision network
+pub type Observations = Array<f32, Dim<[usize; 2]>>;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepInfo {
+    pub tick: usize,
+    pub food_returned_to_nest: f64,
+    pub food_remaining: f64,
+    // Only set on the step that ends the episode, along with config.reward's score for it
+    pub result: Option<SimulationResult>,
+    pub episode_reward: Option<f64>,
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum StepError {
+    EpisodeDone,
+    WrongNumberOfActions { expected: usize, found: usize },
+}
+
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepError::EpisodeDone => write!(f, "the episode has ended, call reset to start a new one"),
+            StepError::WrongNumberOfActions { expected, found } => write!(
+                f, "expected one action per ant, {} in all, but got {}", expected, found
+            ),
+        }
+    }
+}
+
+
+impl std::error::Error for StepError {}
+
+
+// A multi-agent environment for training ant policies outside of the evolution strategies, in
+// the style of Gym: reset to start an episode, then step with one action per ant. An action is
+// the angle in radians the ant turns by before it moves, the same as the decision network's
+// output. The episode ends for all ants together after config.num_steps steps.
+//
+// The per-step rewards don't use config.reward: its terms score a whole episode and most of
+// them can't be split between ants. Instead every ant is rewarded 1 for each load of food it
+// carries into the nest. The last step's StepInfo has the episode scored by config.reward, the
+// same number the evolution strategies train on.
+pub struct ColonyEnv {
+    config: SimulationConfig,
+    environment: Environment,
+    simulation: Simulation,
+}
+
+
+impl ColonyEnv {
+    pub fn new(config: SimulationConfig) -> Result<ColonyEnv, MapError> {
+        let environment = config.environment.build()?;
+        let simulation = ColonyEnv::new_simulation(&config, environment.clone(), config.seed);
+
+        Ok(ColonyEnv { config, environment, simulation })
+    }
+
+    fn new_simulation(config: &SimulationConfig, environment: Environment, seed: u64) -> Simulation {
+        // The colony's own network is never used, the actions passed to step replace it
+        Simulation::from_environment(environment, config.colony.num_ants, &config.ant, config.build_network(seed), seed)
+    }
+
+    pub fn num_agents(&self) -> usize {
+        self.config.colony.num_ants
+    }
+
+    pub fn observation_dimension(&self) -> usize {
+        self.config.input_dimension()
+    }
+
+    pub fn episode_length(&self) -> usize {
+        self.config.num_steps
+    }
+
+    pub fn config(&self) -> &SimulationConfig {
+        &self.config
+    }
+
+    // The running episode, to render it or add observers. Observers are carried over by reset.
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    pub fn simulation_mut(&mut self) -> &mut Simulation {
+        &mut self.simulation
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.simulation.tick >= self.config.num_steps
+    }
+
+    pub fn reset(&mut self, seed: u64) -> Observations {
+        let mut simulation = ColonyEnv::new_simulation(&self.config, self.environment.clone(), seed);
+        for observer in self.simulation.remove_observers() {
+            simulation.add_observer(observer);
+        }
+        if self.simulation.metrics.is_some() {
+            simulation.record_metrics();
+        }
+        self.simulation = simulation;
+
+        let simulation = &mut self.simulation;
+        simulation.colony.observe(&simulation.environment, &mut simulation.rng)
+    }
+
+    pub fn step(&mut self, actions: &[f32]) -> Result<(Observations, Vec<f64>, Vec<bool>, StepInfo), StepError> {
+        if self.is_done() {
+            return Err(StepError::EpisodeDone)
+        }
+        if actions.len() != self.num_agents() {
+            return Err(StepError::WrongNumberOfActions { expected: self.num_agents(), found: actions.len() })
+        }
+
+        let observations = self.simulation.step_with_turns(actions);
+        let mut rewards = vec![0.0; self.num_agents()];
+        for event in self.simulation.colony.events() {
+            if let AntEvent::FoodDelivered { ant, .. } = event {
+                rewards[*ant] += 1.0;
+            }
+        }
+        let done = self.is_done();
+        let result = if done { Some(self.simulation.finish()) } else { None };
+        let info = StepInfo {
+            tick: self.simulation.tick,
+            food_returned_to_nest: self.simulation.environment.food_returned_to_nest,
+            food_remaining: self.simulation.environment.total_food_remaining(),
+            episode_reward: result.as_ref().map(|result| self.config.reward.reward(result)),
+            result,
+        };
+
+        Ok((observations, rewards, vec![done; self.num_agents()], info))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_colony_env() {
+        let mut config = SimulationConfig::from_file("configs/maze.json").unwrap();
+        config.num_steps = 200;
+        let mut env = ColonyEnv::new(config).unwrap();
+        let mut second_env = ColonyEnv::new(env.config().clone()).unwrap();
+        let network = env.config().build_network(7);
+
+        let observations = env.reset(7);
+        assert_eq!(observations.shape(), &[env.num_agents(), env.observation_dimension()]);
+        assert!(observations.iter().all(|x| !x.is_nan()));
+        assert_eq!(second_env.reset(7), observations);
+
+        let mut observations = observations;
+        let mut total_reward = 0.0;
+        let mut steps = 0;
+        loop {
+            let turns: Vec<f32> = network.forward(observations).column(0).to_vec();
+            let (next_observations, rewards, dones, info) = env.step(&turns).unwrap();
+            let (second_observations, second_rewards, _, _) = second_env.step(&turns).unwrap();
+            assert!(next_observations.iter().all(|x| !x.is_nan()));
+            assert_eq!(next_observations, second_observations);
+            assert_eq!(rewards, second_rewards);
+            total_reward += rewards.iter().sum::<f64>();
+            steps += 1;
+            observations = next_observations;
+            if dones[0] {
+                let result = info.result.unwrap();
+                assert_eq!(result.num_iters, 200);
+                assert!((total_reward * 0.1 - result.food_returned_to_nest).abs() < 1e-9);
+                assert_eq!(info.episode_reward, Some(env.config().reward.reward(&result)));
+                break
+            }
+            assert!(info.result.is_none() && info.episode_reward.is_none());
+        }
+        assert_eq!(steps, 200);
+        assert!(env.is_done());
+        assert_eq!(env.step(&vec![0.0; env.num_agents()]).unwrap_err(), StepError::EpisodeDone);
+
+        env.reset(8);
+        assert!(!env.is_done());
+        assert_eq!(env.simulation().tick, 0);
+        assert_eq!(
+            env.step(&[0.0]).unwrap_err(),
+            StepError::WrongNumberOfActions { expected: env.num_agents(), found: 1 }
+        );
+        assert_eq!(env.simulation().tick, 0);
+    }
+}
@@ -0,0 +1,357 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::simulation::environment::{Cell, DiffusionSettings, Environment};
+
+
+// Text maps use one character per cell, one line per row of the arena:
+//   '#'       wall
+//   '.' ' '   empty floor
+//   'N'       nest
+//   'F'       a full cell of food (1.0)
+//   '1'..'9'  a partial cell of food, in tenths (e.g. '5' is 0.5)
+// Lines starting with ';' are comments and empty lines are skipped, but a line of spaces is a
+// row of floor. Errors give the row as the line of the file. Image maps use one pixel per
+// cell, black is a wall, white (or fully transparent) is floor, blue (0, 0, 255) is the nest
+// and pure green is food with the green channel giving the amount.
+//
+// Maps that are rectangular but not square are padded with walls to make them square, and
+// the outermost ring of cells is always made a wall. Saving a map rounds food to the nearest
+// tenth for text maps.
+
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(std::io::Error),
+    Image(String),
+    Empty,
+    NotRectangular { row: usize, expected_width: usize, found_width: usize },
+    UnknownSymbol { symbol: char, row: usize, column: usize },
+    UnknownColor { color: [u8; 4], row: usize, column: usize },
+    NoNest,
+}
+
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io(e) => write!(f, "could not access map file: {}", e),
+            MapError::Image(e) => write!(f, "could not read or write map image: {}", e),
+            MapError::Empty => write!(f, "map contains no cells"),
+            MapError::NotRectangular { row, expected_width, found_width } => write!(
+                f, "map is not rectangular: row {} has {} cells but the first row has {}",
+                row, found_width, expected_width
+            ),
+            MapError::UnknownSymbol { symbol, row, column } => write!(
+                f, "unknown map symbol {:?} at row {}, column {}", symbol, row, column
+            ),
+            MapError::UnknownColor { color, row, column } => write!(
+                f, "unknown map color {:?} at row {}, column {}", color, row, column
+            ),
+            MapError::NoNest => write!(f, "map has no nest cells inside its outer edge, which is always wall"),
+        }
+    }
+}
+
+
+impl std::error::Error for MapError {}
+
+
+impl From<std::io::Error> for MapError {
+    fn from(e: std::io::Error) -> MapError {
+        MapError::Io(e)
+    }
+}
+
+
+fn cell_from_symbol(symbol: char) -> Option<Cell> {
+    let mut cell = Cell::new([0, 0]);
+    match symbol {
+        '#' => cell.is_traversable = false,
+        '.' | ' ' => {},
+        'N' => cell.is_nest = true,
+        'F' => cell.food_amount = 1.0,
+        '1'..='9' => cell.food_amount = symbol.to_digit(10).unwrap() as f64 / 10.,
+        _ => return None,
+    }
+
+    Some(cell)
+}
+
+
+fn cell_from_color(color: [u8; 4]) -> Option<Cell> {
+    let mut cell = Cell::new([0, 0]);
+    match color {
+        [_, _, _, 0] | [255, 255, 255, _] => {},
+        [0, 0, 0, _] => cell.is_traversable = false,
+        [0, 0, 255, _] => cell.is_nest = true,
+        [0, green, 0, _] => cell.food_amount = green as f64 / 255.,
+        _ => return None,
+    }
+
+    Some(cell)
+}
+
+
+// Each row comes with the number it's reported by in errors, its line in a text map
+fn environment_from_rows(rows: Vec<(usize, Vec<Cell>)>, diffusion_settings: DiffusionSettings) -> Result<Environment, MapError> {
+    if rows.is_empty() || rows[0].1.is_empty() {
+        return Err(MapError::Empty)
+    }
+    let width = rows[0].1.len();
+    for (row_number, row) in rows.iter() {
+        if row.len() != width {
+            return Err(MapError::NotRectangular {
+                row: *row_number,
+                expected_width: width,
+                found_width: row.len(),
+            })
+        }
+    }
+    let rows: Vec<Vec<Cell>> = rows.into_iter().map(|(_, row)| row).collect();
+    // Rows of the map run along the x axis of the grid, which is indexed grid[x][y]
+    let arena_size = usize::max(width, rows.len());
+    let mut wall = Cell::new([0, 0]);
+    wall.is_traversable = false;
+    let mut grid = vec![vec![wall; arena_size]; arena_size];
+    for (y, row) in rows.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            grid[x][y] = *cell;
+        }
+    }
+
+    // The outermost cells become walls, so a nest only on the border would be unreachable
+    let environment = Environment::from_grid(grid, diffusion_settings);
+    if environment.nest_location().is_none() {
+        return Err(MapError::NoNest)
+    }
+
+    Ok(environment)
+}
+
+
+pub fn parse_ascii_map(text: &str, diffusion_settings: DiffusionSettings) -> Result<Environment, MapError> {
+    let mut rows: Vec<(usize, Vec<Cell>)> = Vec::new();
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with(';') || line.is_empty() {
+            continue;
+        }
+        let mut row: Vec<Cell> = Vec::new();
+        for (column, symbol) in line.chars().enumerate() {
+            match cell_from_symbol(symbol) {
+                Some(cell) => row.push(cell),
+                None => return Err(MapError::UnknownSymbol { symbol, row: line_index + 1, column: column + 1 }),
+            }
+        }
+        rows.push((line_index + 1, row));
+    }
+
+    environment_from_rows(rows, diffusion_settings)
+}
+
+
+pub fn parse_image_map(image: &image::RgbaImage, diffusion_settings: DiffusionSettings) -> Result<Environment, MapError> {
+    let mut rows: Vec<(usize, Vec<Cell>)> = Vec::new();
+    for y in 0..image.height() {
+        let mut row: Vec<Cell> = Vec::new();
+        for x in 0..image.width() {
+            let color = image.get_pixel(x, y).0;
+            match cell_from_color(color) {
+                Some(cell) => row.push(cell),
+                None => return Err(MapError::UnknownColor { color, row: y as usize + 1, column: x as usize + 1 }),
+            }
+        }
+        rows.push((y as usize + 1, row));
+    }
+
+    environment_from_rows(rows, diffusion_settings)
+}
+
+
+fn symbol_from_cell(cell: &Cell) -> char {
+    let food_tenths = (cell.food_amount * 10.).round() as i32;
+    if !cell.is_traversable {
+        '#'
+    } else if cell.is_nest {
+        'N'
+    } else if food_tenths >= 10 {
+        'F'
+    } else if food_tenths >= 1 {
+        std::char::from_digit(food_tenths as u32, 10).unwrap()
+    } else {
+        '.'
+    }
+}
+
+
+fn color_from_cell(cell: &Cell) -> [u8; 4] {
+    if !cell.is_traversable {
+        [0, 0, 0, 255]
+    } else if cell.is_nest {
+        [0, 0, 255, 255]
+    } else if cell.food_amount > 0.0 {
+        // Green 0 would read back as a wall, so even a crumb of food is at least 1
+        [0, (cell.food_amount.min(1.0) * 255.).round().max(1.) as u8, 0, 255]
+    } else {
+        [255, 255, 255, 255]
+    }
+}
+
+
+pub fn to_ascii_map(environment: &Environment) -> String {
+    let mut text = String::new();
+    for y in 0..environment.size {
+        for x in 0..environment.size {
+            text.push(symbol_from_cell(&environment.grid[x][y]));
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+
+pub fn to_image_map(environment: &Environment) -> image::RgbaImage {
+    image::RgbaImage::from_fn(environment.size as u32, environment.size as u32, |x, y| {
+        image::Rgba(color_from_cell(&environment.grid[x as usize][y as usize]))
+    })
+}
+
+
+fn is_image_path(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension.eq_ignore_ascii_case("png"),
+        None => false,
+    }
+}
+
+
+pub fn save_map<P: AsRef<Path>>(environment: &Environment, path: P) -> Result<(), MapError> {
+    let path = path.as_ref();
+    if is_image_path(path) {
+        to_image_map(environment).save(path).map_err(|e| MapError::Image(e.to_string()))
+    } else {
+        fs::write(path, to_ascii_map(environment)).map_err(MapError::Io)
+    }
+}
+
+
+pub fn load_map<P: AsRef<Path>>(path: P, diffusion_settings: DiffusionSettings) -> Result<Environment, MapError> {
+    let path = path.as_ref();
+    if is_image_path(path) {
+        let image = image::open(path).map_err(|e| MapError::Image(e.to_string()))?;
+        parse_image_map(&image.to_rgba8(), diffusion_settings)
+    } else {
+        let text = fs::read_to_string(path)?;
+        parse_ascii_map(&text, diffusion_settings)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse_ascii_map() {
+        let text = "; a small test arena\n\
+                    #######\n\
+                    #NN...#\n\
+                    #N.#..#\n\
+                    #..#.F#\n\
+                    #....5#\n\
+                    #######\n";
+        let environment = parse_ascii_map(text, DiffusionSettings::new()).unwrap();
+
+        assert_eq!(environment.size, 7);
+        assert!(environment.cell_is_nest([1, 1]));
+        assert!(environment.cell_is_nest([1, 2]));
+        assert!(!environment.cell_is_traversable([3, 2]));
+        assert_eq!(environment.grid[5][3].food_amount, 1.0);
+        assert_eq!(environment.grid[5][4].food_amount, 0.5);
+    }
+
+    #[test]
+    fn test_parse_ascii_map_pads_to_square() {
+        let environment = parse_ascii_map("#####\n#N.F#\n#####\n", DiffusionSettings::new()).unwrap();
+
+        assert_eq!(environment.size, 5);
+        assert!(environment.cell_is_nest([1, 1]));
+        assert!(!environment.cell_is_traversable([1, 4]));
+    }
+
+    #[test]
+    fn test_parse_ascii_map_errors() {
+        match parse_ascii_map("####\n#N.#\n###\n", DiffusionSettings::new()) {
+            Err(MapError::NotRectangular { row: 3, expected_width: 4, found_width: 3 }) => {},
+            _ => panic!("expected a NotRectangular error"),
+        }
+        match parse_ascii_map("####\n#..#\n####\n", DiffusionSettings::new()) {
+            Err(MapError::NoNest) => {},
+            _ => panic!("expected a NoNest error"),
+        }
+        match parse_ascii_map("#N##\nN..#\n####\n", DiffusionSettings::new()) {
+            Err(MapError::NoNest) => {},
+            _ => panic!("expected a NoNest error for a nest on the border"),
+        }
+        match parse_ascii_map("####\n#N?#\n####\n", DiffusionSettings::new()) {
+            Err(MapError::UnknownSymbol { symbol: '?', row: 2, column: 3 }) => {},
+            _ => panic!("expected an UnknownSymbol error"),
+        }
+        // Both errors count comment and empty lines, so rows are the lines of the file
+        match parse_ascii_map("; comment\n####\n\n#N.#\n###\n", DiffusionSettings::new()) {
+            Err(MapError::NotRectangular { row: 5, expected_width: 4, found_width: 3 }) => {},
+            _ => panic!("expected a NotRectangular error on line 5"),
+        }
+        match parse_ascii_map("; comment\n####\n\n#N?#\n####\n", DiffusionSettings::new()) {
+            Err(MapError::UnknownSymbol { symbol: '?', row: 4, column: 3 }) => {},
+            _ => panic!("expected an UnknownSymbol error on line 4"),
+        }
+    }
+
+    #[test]
+    fn test_row_of_spaces_is_floor() {
+        let environment = parse_ascii_map("######\n#N...#\n#    #\n#...F#\n######\n", DiffusionSettings::new()).unwrap();
+
+        assert_eq!(environment.size, 6);
+        assert!(environment.cell_is_traversable([2, 2]));
+        assert_eq!(environment.grid[4][3].food_amount, 1.0);
+    }
+
+    #[test]
+    fn test_map_round_trip() {
+        let environment = load_map("maps/obstacle_course.txt", DiffusionSettings::new()).unwrap();
+        let text = to_ascii_map(&environment);
+        let reloaded = parse_ascii_map(&text, DiffusionSettings::new()).unwrap();
+        let reloaded_image = parse_image_map(&to_image_map(&environment), DiffusionSettings::new()).unwrap();
+
+        for (i, row) in environment.grid.iter().enumerate() {
+            for (j, cell) in row.iter().enumerate() {
+                for reloaded_cell in [reloaded.grid[i][j], reloaded_image.grid[i][j]].iter() {
+                    assert_eq!(cell.is_traversable, reloaded_cell.is_traversable);
+                    assert_eq!(cell.is_nest, reloaded_cell.is_nest);
+                    assert!((cell.food_amount - reloaded_cell.food_amount).abs() < 0.01);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_image_map() {
+        let mut image = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 255, 255, 255]));
+        image.put_pixel(1, 1, image::Rgba([0, 0, 255, 255]));
+        image.put_pixel(2, 2, image::Rgba([0, 255, 0, 255]));
+        let environment = parse_image_map(&image, DiffusionSettings::new()).unwrap();
+
+        assert!(environment.cell_is_nest([1, 1]));
+        assert_eq!(environment.grid[2][2].food_amount, 1.0);
+        assert!(!environment.cell_is_traversable([0, 0]));
+
+        image.put_pixel(2, 1, image::Rgba([10, 20, 30, 255]));
+        match parse_image_map(&image, DiffusionSettings::new()) {
+            Err(MapError::UnknownColor { row: 2, column: 3, .. }) => {},
+            _ => panic!("expected an UnknownColor error"),
+        }
+    }
+}
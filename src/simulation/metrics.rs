@@ -0,0 +1,211 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use ndarray::{Array, Dim};
+use ndarray_npy::{write_npy, WriteNpyError};
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::colony::Colony;
+use crate::simulation::environment::Environment;
+use crate::simulation::observer::SimulationObserver;
+use crate::simulation::simulation::Simulation;
+
+
+// The state of the colony after one tick. Columns in the CSV and NPY exports are in this order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TickMetrics {
+    pub tick: usize,
+    pub food_delivered: f64,
+    pub cumulative_food_delivered: f64,
+    pub ants_carrying_food: usize,
+    pub proportion_explored: f64,
+    pub nest_pheromone_mass: f64,
+    pub food_pheromone_mass: f64,
+    // Euclidean distance in cells from the middle of the nest, 0 if there is no nest
+    pub mean_distance_from_nest: f64,
+}
+
+
+pub const METRICS_COLUMNS: [&str; 8] = [
+    "tick",
+    "food_delivered",
+    "cumulative_food_delivered",
+    "ants_carrying_food",
+    "proportion_explored",
+    "nest_pheromone_mass",
+    "food_pheromone_mass",
+    "mean_distance_from_nest",
+];
+
+
+impl TickMetrics {
+    fn values(&self) -> [f64; 8] {
+        [
+            self.tick as f64,
+            self.food_delivered,
+            self.cumulative_food_delivered,
+            self.ants_carrying_food as f64,
+            self.proportion_explored,
+            self.nest_pheromone_mass,
+            self.food_pheromone_mass,
+            self.mean_distance_from_nest,
+        ]
+    }
+}
+
+
+#[derive(Debug)]
+pub enum MetricsError {
+    Io(io::Error),
+    Npy(WriteNpyError),
+}
+
+
+impl fmt::Display for MetricsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetricsError::Io(e) => write!(f, "could not write metrics: {}", e),
+            MetricsError::Npy(e) => write!(f, "could not write metrics: {}", e),
+        }
+    }
+}
+
+
+impl std::error::Error for MetricsError {}
+
+
+impl From<io::Error> for MetricsError {
+    fn from(e: io::Error) -> MetricsError {
+        MetricsError::Io(e)
+    }
+}
+
+
+// Collects one row of TickMetrics per simulation tick. Deliveries are counted from the food
+// already returned when recording started, so a recorder attached part way through an episode
+// doesn't report everything before it as delivered on its first tick.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MetricsRecorder {
+    ticks: Vec<TickMetrics>,
+    initial_food_delivered: f64,
+}
+
+
+impl MetricsRecorder {
+    pub fn new(environment: &Environment) -> MetricsRecorder {
+        MetricsRecorder {
+            ticks: Vec::new(),
+            initial_food_delivered: environment.food_returned_to_nest,
+        }
+    }
+
+    pub fn ticks(&self) -> &[TickMetrics] {
+        &self.ticks
+    }
+
+    pub fn record(&mut self, tick: usize, environment: &Environment, colony: &Colony) {
+        let previous_food_delivered = match self.ticks.last() {
+            Some(metrics) => metrics.cumulative_food_delivered,
+            None => self.initial_food_delivered,
+        };
+        let ants = colony.ants();
+        let mean_distance_from_nest = match environment.nest_location() {
+            Some(nest) if !ants.is_empty() => {
+                let nest_x = nest[0] as f64 + 0.5;
+                let nest_y = nest[1] as f64 + 0.5;
+                ants.iter()
+                    .map(|ant| (ant.coordinates[0] as f64 - nest_x).hypot(ant.coordinates[1] as f64 - nest_y))
+                    .sum::<f64>() / ants.len() as f64
+            },
+            _ => 0.0,
+        };
+
+        self.ticks.push(TickMetrics {
+            tick,
+            food_delivered: environment.food_returned_to_nest - previous_food_delivered,
+            cumulative_food_delivered: environment.food_returned_to_nest,
+            ants_carrying_food: ants.iter().filter(|ant| ant.has_food).count(),
+            proportion_explored: environment.num_cells_visited() as f64 / environment.size.pow(2) as f64,
+            nest_pheromone_mass: environment.total_nest_pheromone(),
+            food_pheromone_mass: environment.total_food_pheromone(),
+            mean_distance_from_nest,
+        });
+    }
+
+    // One row per tick with the columns in METRICS_COLUMNS order
+    pub fn to_array(&self) -> Array<f64, Dim<[usize; 2]>> {
+        let values: Vec<f64> = self.ticks.iter().flat_map(|metrics| metrics.values().to_vec()).collect();
+
+        Array::from_shape_vec((self.ticks.len(), METRICS_COLUMNS.len()), values).unwrap()
+    }
+
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", METRICS_COLUMNS.join(","))?;
+        for metrics in self.ticks.iter() {
+            let row: Vec<String> = metrics.values().iter().map(|value| value.to_string()).collect();
+            writeln!(writer, "{}", row.join(","))?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn write_npy<P: AsRef<Path>>(&self, path: P) -> Result<(), WriteNpyError> {
+        write_npy(path, &self.to_array())
+    }
+
+    // Paths ending in .npy get a float64 array, anything else is written as CSV
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MetricsError> {
+        let path = path.as_ref();
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("npy") => self.write_npy(path).map_err(MetricsError::Npy),
+            _ => Ok(self.write_csv(path)?),
+        }
+    }
+}
+
+
+impl SimulationObserver for MetricsRecorder {
+    fn on_tick_end(&mut self, simulation: &Simulation) {
+        self.record(simulation.tick, &simulation.environment, &simulation.colony);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neural_network::mlp::{default_activations, MLP};
+    #[test]
+    fn test_metrics_recorder() {
+        let decision_network: MLP = MLP::new(38, vec![16, 1], default_activations(2));
+        let mut simulation = Simulation::new(30, 0.99, 20, decision_network, 0);
+        simulation.record_metrics();
+        let result = simulation.run(50);
+
+        let metrics = simulation.metrics.as_ref().unwrap();
+        assert_eq!(metrics.ticks().len(), 50);
+        assert_eq!(metrics.ticks()[0].tick, 1);
+        let last = metrics.ticks().last().unwrap();
+        assert_eq!(last.cumulative_food_delivered, result.food_returned_to_nest);
+        assert_eq!(last.proportion_explored, result.proportion_explored);
+        let delivered: f64 = metrics.ticks().iter().map(|metrics| metrics.food_delivered).sum();
+        assert!((delivered - result.food_returned_to_nest).abs() < 1e-9);
+        assert!(metrics.ticks().iter().all(|metrics| metrics.ants_carrying_food <= 20));
+
+        let array = metrics.to_array();
+        assert_eq!(array.shape(), &[50, METRICS_COLUMNS.len()]);
+        assert_eq!(array[[49, 0]], 50.0);
+
+        let path = std::env::temp_dir().join("test_metrics_recorder.csv");
+        metrics.save(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 51);
+        assert_eq!(lines[0], METRICS_COLUMNS.join(","));
+        assert!(lines[1].starts_with("1,"));
+    }
+}
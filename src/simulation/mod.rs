@@ -1,5 +1,16 @@
 pub mod colony;
+pub mod controller;
+pub mod editor;
 pub mod environment;
+pub mod generators;
+pub mod gym;
+pub mod map;
+pub mod metrics;
+pub mod observer;
+pub mod render;
+pub mod replay;
+pub mod terminal_view;
 pub mod world_view;
 pub mod simulation;
+pub mod snapshot;
 pub mod utils;
\ No newline at end of file
@@ -0,0 +1,120 @@
+use std::sync::{Arc, Mutex};
+
+use crate::simulation::colony::AntEvent;
+use crate::simulation::simulation::{Simulation, SimulationResult};
+
+
+// Callbacks for instrumenting a simulation without changing it. Every callback gets the
+// simulation as it is at that point, so tick start sees the state before the tick and the ant
+// events and tick end see it after. Ant events for a tick are reported in ant order, after the
+// whole colony has moved, and are followed by on_tick_end. on_episode_end is called at the end
+// of Simulation::run and by Simulation::finish, which ends an episode stepped by hand (as the gym
+// environment and replays do). Stepping without calling finish never ends the episode.
+pub trait SimulationObserver: Send {
+    fn on_tick_start(&mut self, _simulation: &Simulation) {}
+
+    fn on_tick_end(&mut self, _simulation: &Simulation) {}
+
+    fn on_food_picked_up(&mut self, _simulation: &Simulation, _ant: usize, _position: [f32; 2]) {}
+
+    fn on_food_delivered(&mut self, _simulation: &Simulation, _ant: usize, _position: [f32; 2]) {}
+
+    fn on_blocked(&mut self, _simulation: &Simulation, _ant: usize, _position: [f32; 2]) {}
+
+    fn on_episode_end(&mut self, _simulation: &Simulation, _result: &SimulationResult) {}
+
+    fn on_ant_event(&mut self, simulation: &Simulation, event: &AntEvent) {
+        match *event {
+            AntEvent::FoodPickedUp { ant, position } => self.on_food_picked_up(simulation, ant, position),
+            AntEvent::FoodDelivered { ant, position } => self.on_food_delivered(simulation, ant, position),
+            AntEvent::Blocked { ant, position } => self.on_blocked(simulation, ant, position),
+        }
+    }
+}
+
+
+// Lets the caller keep a handle on an observer after giving it to a simulation, to read what it
+// collected
+impl<T: SimulationObserver> SimulationObserver for Arc<Mutex<T>> {
+    fn on_tick_start(&mut self, simulation: &Simulation) {
+        self.lock().unwrap().on_tick_start(simulation)
+    }
+
+    fn on_tick_end(&mut self, simulation: &Simulation) {
+        self.lock().unwrap().on_tick_end(simulation)
+    }
+
+    fn on_episode_end(&mut self, simulation: &Simulation, result: &SimulationResult) {
+        self.lock().unwrap().on_episode_end(simulation, result)
+    }
+
+    fn on_ant_event(&mut self, simulation: &Simulation, event: &AntEvent) {
+        self.lock().unwrap().on_ant_event(simulation, event)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neural_network::mlp::{default_activations, MLP};
+
+    #[derive(Default)]
+    struct Counter {
+        ticks_started: usize,
+        ticks_ended: usize,
+        picked_up: usize,
+        delivered: usize,
+        blocked: usize,
+        episodes: usize,
+    }
+
+    impl SimulationObserver for Counter {
+        fn on_tick_start(&mut self, simulation: &Simulation) {
+            assert_eq!(simulation.tick, self.ticks_started);
+            self.ticks_started += 1;
+        }
+
+        fn on_tick_end(&mut self, simulation: &Simulation) {
+            self.ticks_ended += 1;
+            assert_eq!(simulation.tick, self.ticks_ended);
+        }
+
+        fn on_food_picked_up(&mut self, _simulation: &Simulation, _ant: usize, _position: [f32; 2]) {
+            self.picked_up += 1;
+        }
+
+        fn on_food_delivered(&mut self, simulation: &Simulation, ant: usize, position: [f32; 2]) {
+            assert!(simulation.environment.cell_is_nest([position[0] as usize, position[1] as usize]));
+            assert!(!simulation.colony.ants()[ant].has_food);
+            self.delivered += 1;
+        }
+
+        fn on_blocked(&mut self, _simulation: &Simulation, _ant: usize, _position: [f32; 2]) {
+            self.blocked += 1;
+        }
+
+        fn on_episode_end(&mut self, _simulation: &Simulation, result: &SimulationResult) {
+            assert_eq!(result.num_iters, self.ticks_ended);
+            self.episodes += 1;
+        }
+    }
+
+    #[test]
+    fn test_observer_callbacks() {
+        let decision_network: MLP = MLP::new(38, vec![16, 1], default_activations(2));
+        let mut simulation = Simulation::new(20, 0.99, 50, decision_network, 3);
+        let counter = Arc::new(Mutex::new(Counter::default()));
+        simulation.add_observer(Box::new(counter.clone()));
+        let result = simulation.run(300);
+
+        let counter = counter.lock().unwrap();
+        assert_eq!(counter.ticks_started, 300);
+        assert_eq!(counter.ticks_ended, 300);
+        assert_eq!(counter.episodes, 1);
+        // Every delivery adds 0.1 food to the nest
+        assert_eq!(counter.delivered, (result.food_returned_to_nest * 10.0).round() as usize);
+        assert!(counter.picked_up >= counter.delivered);
+        assert!(counter.blocked > 0);
+    }
+}
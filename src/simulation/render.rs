@@ -0,0 +1,220 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use graphics::types::Color;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, ImageResult, Rgba, RgbaImage};
+
+use crate::simulation::colony::Colony;
+use crate::simulation::environment::Environment;
+use crate::simulation::observer::SimulationObserver;
+use crate::simulation::simulation::Simulation;
+use crate::simulation::world_view::{WorldView, WorldViewSettings};
+
+
+// Draws the same layers as WorldView, but into an image in memory rather than an OpenGL window,
+// so episodes can be rendered on machines without a display or GPU. Pixel (x, y) of the image
+// is the same point as in the visualizer's window.
+pub struct FrameRenderer {
+    world_view: WorldView,
+}
+
+
+impl FrameRenderer {
+    pub fn new(settings: WorldViewSettings) -> FrameRenderer {
+        FrameRenderer {
+            world_view: WorldView::new(settings),
+        }
+    }
+
+    pub fn settings(&self) -> &WorldViewSettings {
+        &self.world_view.settings
+    }
+
+    pub fn render(&self, environment: &Environment, colony: &Colony) -> RgbaImage {
+        let settings = self.settings();
+        let pixel_size = settings.pixel_size as u32;
+        let side = environment.size as u32 * pixel_size;
+        // The visualizer's window clears to black
+        let mut image = RgbaImage::from_pixel(side, side, Rgba([0, 0, 0, 255]));
+
+        for (i, grid_row) in environment.grid.iter().enumerate() {
+            for (j, cell) in grid_row.iter().enumerate() {
+                self.world_view.for_each_cell_color(cell, |color| {
+                    for x in i as u32 * pixel_size..(i as u32 + 1) * pixel_size {
+                        for y in j as u32 * pixel_size..(j as u32 + 1) * pixel_size {
+                            blend(image.get_pixel_mut(x, y), color);
+                        }
+                    }
+                });
+            }
+        }
+        if settings.show_ants {
+            for ant in colony.ants().iter() {
+                let color = if ant.has_food { settings.ant_with_food_color } else { settings.ant_color };
+                fill_triangle(&mut image, &self.world_view.ant_triangle(ant), color);
+            }
+        }
+
+        image
+    }
+}
+
+
+// Draws color over the pixel with its alpha, like the visualizer's alpha blending
+pub(crate) fn blend(pixel: &mut Rgba<u8>, color: Color) {
+    let alpha = color[3].clamp(0.0, 1.0);
+    for (channel, above) in pixel.0.iter_mut().zip(color.iter()).take(3) {
+        let below = *channel as f32 / 255.0;
+        *channel = ((above.clamp(0.0, 1.0) * alpha + below * (1.0 - alpha)) * 255.0).round() as u8;
+    }
+}
+
+
+// Fills every pixel whose centre is inside the triangle
+fn fill_triangle(image: &mut RgbaImage, points: &[[f64; 2]; 3], color: Color) {
+    let edge = |a: [f64; 2], b: [f64; 2], p: [f64; 2]| (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0]);
+    let min_x = points.iter().map(|p| p[0]).fold(f64::INFINITY, f64::min).floor().max(0.0) as u32;
+    let min_y = points.iter().map(|p| p[1]).fold(f64::INFINITY, f64::min).floor().max(0.0) as u32;
+    let max_x = points.iter().map(|p| p[0]).fold(f64::NEG_INFINITY, f64::max).ceil().max(0.0) as u32;
+    let max_y = points.iter().map(|p| p[1]).fold(f64::NEG_INFINITY, f64::max).ceil().max(0.0) as u32;
+
+    for x in min_x..max_x.min(image.width()) {
+        for y in min_y..max_y.min(image.height()) {
+            let centre = [x as f64 + 0.5, y as f64 + 0.5];
+            let edges = [
+                edge(points[0], points[1], centre),
+                edge(points[1], points[2], centre),
+                edge(points[2], points[0], centre),
+            ];
+            // Either winding order counts as inside
+            if edges.iter().all(|e| *e >= 0.0) || edges.iter().all(|e| *e <= 0.0) {
+                blend(image.get_pixel_mut(x, y), color);
+            }
+        }
+    }
+}
+
+
+pub enum FrameOutput {
+    // One numbered PNG per frame in the directory
+    Png(PathBuf),
+    Gif(Box<GifEncoder<BufWriter<File>>>),
+}
+
+
+// Renders a frame every `interval` ticks as a simulation runs. Observers can't return errors, so
+// the first one is kept, no more frames are written after it, and finish reports it.
+pub struct EpisodeRenderer {
+    renderer: FrameRenderer,
+    output: FrameOutput,
+    interval: usize,
+    frame_delay_ms: u32,
+    ticks_since_frame: usize,
+    frames_written: usize,
+    error: Option<image::ImageError>,
+}
+
+
+impl EpisodeRenderer {
+    pub fn png_frames<P: AsRef<Path>>(renderer: FrameRenderer, directory: P, interval: usize) -> ImageResult<EpisodeRenderer> {
+        fs::create_dir_all(&directory)?;
+
+        Ok(EpisodeRenderer::new(renderer, FrameOutput::Png(directory.as_ref().to_path_buf()), interval, 0))
+    }
+
+    // Each frame is shown for frame_delay_ms and the animation loops
+    pub fn gif<P: AsRef<Path>>(renderer: FrameRenderer, path: P, interval: usize, frame_delay_ms: u32) -> ImageResult<EpisodeRenderer> {
+        let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        Ok(EpisodeRenderer::new(renderer, FrameOutput::Gif(Box::new(encoder)), interval, frame_delay_ms))
+    }
+
+    fn new(renderer: FrameRenderer, output: FrameOutput, interval: usize, frame_delay_ms: u32) -> EpisodeRenderer {
+        EpisodeRenderer {
+            renderer,
+            output,
+            interval: usize::max(interval, 1),
+            frame_delay_ms,
+            ticks_since_frame: 0,
+            frames_written: 0,
+            error: None,
+        }
+    }
+
+    pub fn frames_written(&self) -> usize {
+        self.frames_written
+    }
+
+    pub fn render_frame(&mut self, simulation: &Simulation) -> ImageResult<()> {
+        let image = self.renderer.render(&simulation.environment, &simulation.colony);
+        match &mut self.output {
+            FrameOutput::Png(directory) => image.save(directory.join(format!("frame_{:06}.png", simulation.tick)))?,
+            FrameOutput::Gif(encoder) => encoder.encode_frame(
+                Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(self.frame_delay_ms, 1))
+            )?,
+        }
+        self.frames_written += 1;
+
+        Ok(())
+    }
+
+    // Returns the number of frames written. A GIF is only complete once this has been called.
+    pub fn finish(self) -> ImageResult<usize> {
+        if let Some(e) = self.error {
+            return Err(e)
+        }
+
+        // Dropping the encoder writes the GIF trailer
+        Ok(self.frames_written)
+    }
+}
+
+
+impl SimulationObserver for EpisodeRenderer {
+    fn on_tick_end(&mut self, simulation: &Simulation) {
+        self.ticks_since_frame += 1;
+        if self.ticks_since_frame < self.interval || self.error.is_some() {
+            return
+        }
+        self.ticks_since_frame = 0;
+        if let Err(e) = self.render_frame(simulation) {
+            self.error = Some(e);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neural_network::mlp::{default_activations, MLP};
+    #[test]
+    fn test_render_frame() {
+        let decision_network: MLP = MLP::new(38, vec![16, 1], default_activations(2));
+        let mut simulation = Simulation::new(20, 0.99, 10, decision_network, 0);
+        simulation.run(5);
+        let mut settings = WorldViewSettings::new();
+        settings.show_ants = false;
+        let image = FrameRenderer::new(settings).render(&simulation.environment, &simulation.colony);
+
+        assert_eq!(image.dimensions(), (80, 80));
+        // The arena's edge is wall, drawn opaque grey
+        assert_eq!(image.get_pixel(0, 0), &Rgba([128, 128, 128, 255]));
+        let nest = simulation.environment.nest_location().unwrap();
+        let nest_pixel = image.get_pixel(nest[0] as u32 * 4 + 1, nest[1] as u32 * 4 + 1);
+        assert_eq!(nest_pixel, &Rgba([0, 0, 128, 255]));
+
+        let with_ants = FrameRenderer::new(WorldViewSettings::new()).render(&simulation.environment, &simulation.colony);
+        assert_ne!(with_ants, image);
+
+        let mut image = RgbaImage::from_pixel(8, 8, Rgba([0, 0, 0, 255]));
+        fill_triangle(&mut image, &[[0.0, 0.0], [0.0, 8.0], [8.0, 0.0]], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.get_pixel(1, 1), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(3, 3), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(4, 4), &Rgba([0, 0, 0, 255]));
+        assert_eq!(image.get_pixel(7, 7), &Rgba([0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,316 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::SimulationConfig;
+use crate::file_format::{decode_body, encode_body, FileError, FileFormat};
+use crate::neural_network::mlp::{default_activations, MLP};
+use crate::simulation::colony::Colony;
+use crate::simulation::observer::SimulationObserver;
+use crate::simulation::simulation::{Simulation, SimulationResult};
+use crate::simulation::snapshot::SimulationSnapshot;
+
+
+// Replay files have a ReplayHeader, and the keyframes and directions as their body
+const REPLAY_FILE: FileFormat = FileFormat {
+    name: "replay",
+    magic: b"ANTREPLAY\n",
+    version: 2,
+};
+
+
+// Written as a line of JSON at the top of the file, so a replay can be identified with head
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub format_version: u32,
+    // The config and seed the episode was started from, for reference. Replaying only needs the
+    // keyframes, so episodes on edited or generated worlds play back the same.
+    pub config: Option<SimulationConfig>,
+    pub seed: u64,
+    pub num_ants: usize,
+    // The tick recording started on and how many ticks were recorded after it
+    pub start_tick: usize,
+    pub num_ticks: usize,
+    pub keyframe_interval: usize,
+}
+
+
+// A snapshot of everything about the simulation that changes from tick to tick. Replays never run
+// the network, so the colony gets a small placeholder one instead, and metrics aren't kept.
+fn keyframe(simulation: &Simulation) -> SimulationSnapshot {
+    let decision_network = MLP::new(1, vec![1], default_activations(1));
+
+    SimulationSnapshot {
+        environment: simulation.environment.clone(),
+        colony: Colony::from_ants(simulation.colony.ants().to_vec(), decision_network),
+        rng: simulation.rng.clone(),
+        tick: simulation.tick,
+        first_delivery_tick: simulation.first_delivery_tick,
+        metrics: None,
+    }
+}
+
+
+// A recorded episode. The simulation only depends on its state, its random number generator and
+// the direction every ant moves in, so recording the directions each tick is enough to play an
+// episode back exactly without the decision network. Keyframes of the full state are kept every
+// keyframe_interval ticks so any tick can be reached without playing from the start.
+pub struct Replay {
+    header: ReplayHeader,
+    keyframes: Vec<SimulationSnapshot>,
+    // One direction per ant for every recorded tick, the ones the ants moved in, then the ones
+    // they were left facing at the end
+    directions: Vec<Vec<[f32; 2]>>,
+}
+
+
+impl Replay {
+    pub fn header(&self) -> &ReplayHeader {
+        &self.header
+    }
+
+    pub fn start_tick(&self) -> usize {
+        self.header.start_tick
+    }
+
+    pub fn end_tick(&self) -> usize {
+        self.header.start_tick + self.header.num_ticks
+    }
+
+    // The simulation as it was at the start of the tick, clamped to the recorded ticks
+    pub fn simulation_at(&self, tick: usize) -> Simulation {
+        let tick = tick.clamp(self.start_tick(), self.end_tick());
+        let keyframe = self.keyframes.iter().rev().find(|keyframe| keyframe.tick <= tick).unwrap();
+        let mut simulation = Simulation::from_snapshot(keyframe.clone());
+        while simulation.tick < tick {
+            self.play_tick(&mut simulation);
+        }
+
+        simulation
+    }
+
+    // Ants are left facing the way they move on the next tick, which is where the decision
+    // network turned them to in the original episode
+    fn play_tick(&self, simulation: &mut Simulation) {
+        let i = simulation.tick - self.start_tick();
+        simulation.step_with_directions(&self.directions[i], &self.directions[i + 1]);
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), FileError> {
+        REPLAY_FILE.write(path, &self.header, &encode_body(&(&self.keyframes, &self.directions))?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay, FileError> {
+        let (header, body): (ReplayHeader, _) = REPLAY_FILE.read(path)?;
+        let (keyframes, directions): (Vec<SimulationSnapshot>, Vec<Vec<[f32; 2]>>) = decode_body(&body)?;
+
+        if keyframes.first().map(|keyframe| keyframe.tick) != Some(header.start_tick) {
+            return Err(FileError::Format("the first keyframe isn't at the start of the episode".to_string()))
+        }
+        let end_tick = header.start_tick + header.num_ticks;
+        if keyframes.windows(2).any(|pair| pair[0].tick >= pair[1].tick) || keyframes.iter().any(|keyframe| keyframe.tick > end_tick) {
+            return Err(FileError::Format("keyframes are out of order".to_string()))
+        }
+        if keyframes.iter().any(|keyframe| keyframe.colony.ants().len() != header.num_ants) {
+            return Err(FileError::Format(format!("a keyframe doesn't have {} ants", header.num_ants)))
+        }
+        for keyframe in keyframes.iter() {
+            keyframe.validate().map_err(FileError::Format)?;
+        }
+        if directions.len() != header.num_ticks + 1 {
+            return Err(FileError::Format(format!(
+                "header says {} ticks but the file has {}", header.num_ticks, directions.len().saturating_sub(1)
+            )))
+        }
+        if directions.iter().any(|tick_directions| tick_directions.len() != header.num_ants) {
+            return Err(FileError::Format(format!("a tick doesn't have {} directions", header.num_ants)))
+        }
+
+        Ok(Replay { header, keyframes, directions })
+    }
+}
+
+
+// Records every following tick of a simulation into a Replay, starting from its current state.
+// Changes made to the world from outside the simulation, like the visualizer's editor, aren't
+// recorded and make the replay diverge until the next keyframe.
+pub struct EpisodeRecorder {
+    replay: Replay,
+    ticks_since_keyframe: usize,
+}
+
+
+impl EpisodeRecorder {
+    pub fn new(simulation: &Simulation, config: Option<SimulationConfig>, seed: u64, keyframe_interval: usize) -> EpisodeRecorder {
+        let header = ReplayHeader {
+            format_version: REPLAY_FILE.version,
+            config,
+            seed,
+            num_ants: simulation.colony.ants().len(),
+            start_tick: simulation.tick,
+            num_ticks: 0,
+            keyframe_interval: usize::max(keyframe_interval, 1),
+        };
+
+        EpisodeRecorder {
+            replay: Replay {
+                header,
+                keyframes: vec![keyframe(simulation)],
+                directions: vec![simulation.colony.directions()],
+            },
+            ticks_since_keyframe: 0,
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn into_replay(self) -> Replay {
+        self.replay
+    }
+}
+
+
+impl SimulationObserver for EpisodeRecorder {
+    // The last directions are the ones the ants were facing at the end of the previous tick.
+    // Turns passed to step_with_turns may have changed them since, and these are the directions
+    // the ants are about to move in.
+    fn on_tick_start(&mut self, simulation: &Simulation) {
+        *self.replay.directions.last_mut().unwrap() = simulation.colony.directions();
+    }
+
+    fn on_tick_end(&mut self, simulation: &Simulation) {
+        self.replay.directions.push(simulation.colony.directions());
+        self.replay.header.num_ticks += 1;
+        self.ticks_since_keyframe += 1;
+        if self.ticks_since_keyframe >= self.replay.header.keyframe_interval {
+            self.replay.keyframes.push(keyframe(simulation));
+            self.ticks_since_keyframe = 0;
+        }
+    }
+}
+
+
+// Plays a replay forwards tick by tick, and jumps to any recorded tick by restoring the keyframe
+// before it. Observers added to the player are kept when seeking. The simulation itself is only
+// lent out read only: its network is a placeholder, so stepping it other than through the player
+// would panic.
+pub struct ReplayPlayer {
+    replay: Replay,
+    simulation: Simulation,
+}
+
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> ReplayPlayer {
+        let simulation = replay.simulation_at(replay.start_tick());
+
+        ReplayPlayer { replay, simulation }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn SimulationObserver>) {
+        self.simulation.add_observer(observer);
+    }
+
+    // Ends the episode at the current tick, telling the observers, and sums it up
+    pub fn finish(&mut self) -> SimulationResult {
+        self.simulation.finish()
+    }
+
+    pub fn tick(&self) -> usize {
+        self.simulation.tick
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.simulation.tick >= self.replay.end_tick()
+    }
+
+    // Returns false without stepping once the end of the replay is reached
+    pub fn step(&mut self) -> bool {
+        if self.is_finished() {
+            return false
+        }
+        self.replay.play_tick(&mut self.simulation);
+
+        true
+    }
+
+    pub fn seek(&mut self, tick: usize) {
+        // Stepping forwards from the current tick is cheaper when no keyframe is in between
+        let keyframe_interval = self.replay.header.keyframe_interval;
+        if tick < self.simulation.tick || tick - self.simulation.tick > keyframe_interval {
+            let mut simulation = self.replay.simulation_at(tick);
+            for observer in self.simulation.remove_observers() {
+                simulation.add_observer(observer);
+            }
+            self.simulation = simulation;
+        }
+        while self.simulation.tick < tick && self.step() {}
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn state(simulation: &Simulation) -> (Vec<[f32; 2]>, Vec<[f32; 2]>, u64) {
+        let positions = simulation.colony.ants().iter().map(|ant| [ant.coordinates[0], ant.coordinates[1]]).collect();
+
+        (positions, simulation.colony.directions(), simulation.environment.total_food_pheromone().to_bits())
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut config = SimulationConfig::from_file("configs/maze.json").unwrap();
+        config.colony.num_ants = 10;
+        let mut simulation = Simulation::from_config(&config, config.build_network(config.seed), 3).unwrap();
+        let recorder = Arc::new(Mutex::new(EpisodeRecorder::new(&simulation, Some(config.clone()), 3, 25)));
+        simulation.add_observer(Box::new(recorder.clone()));
+        let mut states = Vec::new();
+        for _ in 0..100 {
+            simulation.step();
+            states.push(state(&simulation));
+        }
+        let result = simulation.finish();
+        drop(simulation);
+
+        let path = std::env::temp_dir().join("test_record_and_replay.replay");
+        recorder.lock().unwrap().replay().save(&path).unwrap();
+        let replay = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.header().num_ticks, 100);
+        assert_eq!(replay.header().seed, 3);
+        assert_eq!(replay.keyframes.len(), 5);
+
+        let mut player = ReplayPlayer::new(replay);
+        let mut replayed_states = Vec::new();
+        while player.step() {
+            replayed_states.push(state(player.simulation()));
+        }
+        assert_eq!(player.tick(), 100);
+        assert_eq!(replayed_states, states);
+        let replayed_result = player.finish();
+        assert_eq!(replayed_result.food_returned_to_nest, result.food_returned_to_nest);
+        assert_eq!(replayed_result.distance_travelled, result.distance_travelled);
+
+        player.seek(40);
+        assert_eq!(player.tick(), 40);
+        assert_eq!(state(player.simulation()), states[39]);
+        player.seek(10);
+        player.seek(60);
+        assert_eq!(state(player.simulation()), states[59]);
+        player.seek(1000);
+        assert!(player.is_finished());
+    }
+}
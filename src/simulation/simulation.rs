@@ -1,49 +1,198 @@
 use std::f64;
 
-use crate::simulation::colony::Colony;
+use ndarray::{Array, Dim};
+use piston::input::GenericEvent;
+use rand::SeedableRng;
+
+use crate::config::SimulationConfig;
+use crate::simulation::colony::{AntSettings, Colony};
 use crate::simulation::environment::Environment;
+use crate::simulation::map::MapError;
+use crate::simulation::metrics::MetricsRecorder;
+use crate::simulation::observer::SimulationObserver;
+use crate::simulation::utils::SimulationRng;
 use crate::neural_network::mlp::MLP;
 
 
 pub struct Simulation {
     pub environment: Environment,
     pub colony: Colony,
+    pub rng: SimulationRng,
+    pub tick: usize,
+    // The tick on which food first made it back to the nest
+    pub first_delivery_tick: Option<usize>,
+    // Per-tick time series, only kept once record_metrics has been called
+    pub metrics: Option<MetricsRecorder>,
+    observers: Vec<Box<dyn SimulationObserver>>,
 }
 
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct SimulationResult {
     pub num_iters: usize,
     pub food_returned_to_nest: f64,
     pub food_remaining: f64,
-    pub proportion_explored: f64
+    pub proportion_explored: f64,
+    pub num_ants: usize,
+    pub first_delivery_tick: Option<usize>,
+    // Summed over every ant in the colony
+    pub distance_travelled: f64,
+    pub pheromone_deposited: f64,
 }
 
 
 impl Simulation {
-    pub fn new(arena_size: usize, diffusion_rate: f64, num_ants: usize, decision_network: MLP) -> Simulation {
+    pub fn new(arena_size: usize, diffusion_rate: f64, num_ants: usize, decision_network: MLP, seed: u64) -> Simulation {
+        let mut rng = SimulationRng::seed_from_u64(seed);
         let environment = Environment::new(arena_size, diffusion_rate);
-        let colony = Colony::new(num_ants, decision_network);
+        let colony = Colony::new(num_ants, decision_network, &mut rng);
+
+        Simulation::from_parts(environment, colony, rng)
+    }
+
+    pub fn from_environment(
+        environment: Environment,
+        num_ants: usize,
+        ant_settings: &AntSettings,
+        decision_network: MLP,
+        seed: u64
+    ) -> Simulation {
+        let mut rng = SimulationRng::seed_from_u64(seed);
+        let nest_location = environment.nest_location().expect("environment has no nest");
+        let colony = Colony::at_location(num_ants, decision_network, nest_location, ant_settings, &mut rng);
 
+        Simulation::from_parts(environment, colony, rng)
+    }
+
+    // A simulation at tick 0 with no observers, around a world and colony built elsewhere
+    pub fn from_parts(environment: Environment, colony: Colony, rng: SimulationRng) -> Simulation {
         Simulation {
             environment,
             colony,
+            rng,
+            tick: 0,
+            first_delivery_tick: None,
+            metrics: None,
+            observers: Vec::new(),
+        }
+    }
+
+    pub fn from_config(config: &SimulationConfig, decision_network: MLP, seed: u64) -> Result<Simulation, MapError> {
+        let environment = config.environment.build()?;
+
+        Ok(Simulation::from_environment(environment, config.colony.num_ants, &config.ant, decision_network, seed))
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn SimulationObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn remove_observers(&mut self) -> Vec<Box<dyn SimulationObserver>> {
+        std::mem::take(&mut self.observers)
+    }
+
+    fn notify<F: Fn(&mut dyn SimulationObserver, &Simulation)>(&mut self, callback: F) {
+        // The observers are moved out while they're called so they can be given the simulation
+        let mut metrics = self.metrics.take();
+        let mut observers = std::mem::take(&mut self.observers);
+        if let Some(metrics) = metrics.as_mut() {
+            callback(metrics, self);
+        }
+        for observer in observers.iter_mut() {
+            callback(observer.as_mut(), self);
+        }
+        self.metrics = metrics;
+        self.observers = observers;
+    }
+
+    pub fn step(&mut self) {
+        self.advance(|colony, environment, rng| colony.update(environment, rng));
+    }
+
+    // Steps with the ants' decisions made outside the simulation: each ant turns by the matching
+    // angle in radians, then moves. Returns what every ant perceives afterwards, one row per ant,
+    // which is what the decision network would otherwise have been given.
+    pub fn step_with_turns(&mut self, turns: &[f32]) -> Array<f32, Dim<[usize; 2]>> {
+        self.colony.turn_ants(turns);
+        let mut observations = None;
+        self.advance(|colony, environment, rng| observations = Some(colony.move_ants(environment, rng)));
+
+        observations.unwrap()
+    }
+
+    // Steps with every ant moving along the given direction and then turning to face
+    // directions_after, as recorded by an EpisodeRecorder. Nothing goes through the decision
+    // network.
+    pub fn step_with_directions(&mut self, directions: &[[f32; 2]], directions_after: &[[f32; 2]]) {
+        self.advance(|colony, environment, rng| {
+            colony.set_directions(directions);
+            colony.move_ants(environment, rng);
+            colony.set_directions(directions_after);
+        });
+    }
+
+    fn advance<F: FnOnce(&mut Colony, &mut Environment, &mut SimulationRng)>(&mut self, update_colony: F) {
+        self.notify(|observer, simulation| observer.on_tick_start(simulation));
+        self.environment.update();
+        update_colony(&mut self.colony, &mut self.environment, &mut self.rng);
+        self.tick += 1;
+        if self.first_delivery_tick.is_none() && self.environment.food_returned_to_nest > 0.0 {
+            self.first_delivery_tick = Some(self.tick);
+        }
+        self.notify(|observer, simulation| {
+            for event in simulation.colony.events() {
+                observer.on_ant_event(simulation, event);
+            }
+            observer.on_tick_end(simulation);
+        });
+    }
+
+    // Starts recording TickMetrics on every following step
+    pub fn record_metrics(&mut self) {
+        if self.metrics.is_none() {
+            self.metrics = Some(MetricsRecorder::new(&self.environment));
         }
     }
 
+    pub fn update_piston<E: GenericEvent>(&mut self, e: &E) {
+        if let Some(_) = e.update_args() {
+            self.step();
+        }
+    }
+
+    // The result's num_iters is num_steps, even if the simulation had already been stepped
     pub fn run(&mut self, num_steps: usize) -> SimulationResult {
         let mut i = 0;
         while i < num_steps {
-            self.environment.update();
-            self.colony.update(&mut self.environment);
+            self.step();
             i += 1;
         };
 
-        SimulationResult::new(
-            i, 
-            self.environment.food_returned_to_nest,
-            self.environment.total_food_remaining(),
-            self.environment.num_cells_visited() as f64/ self.environment.size.pow(2) as f64 
-        )
+        self.end_episode(i)
+    }
+
+    // Ends the episode, telling the observers, and sums it up. For episodes stepped one tick at a
+    // time, so num_iters counts every tick since the simulation started.
+    pub fn finish(&mut self) -> SimulationResult {
+        self.end_episode(self.tick)
+    }
+
+    fn end_episode(&mut self, num_iters: usize) -> SimulationResult {
+        let result = SimulationResult {
+            num_ants: self.colony.ants().len(),
+            first_delivery_tick: self.first_delivery_tick,
+            distance_travelled: self.colony.distance_travelled(),
+            pheromone_deposited: self.environment.pheromone_deposited,
+            ..SimulationResult::new(
+                num_iters,
+                self.environment.food_returned_to_nest,
+                self.environment.total_food_remaining(),
+                self.environment.num_cells_visited() as f64/ self.environment.size.pow(2) as f64 
+            )
+        };
+        self.notify(|observer, simulation| observer.on_episode_end(simulation, &result));
+
+        result
     }
 }
 
@@ -54,7 +203,11 @@ impl SimulationResult {
             num_iters, 
             food_returned_to_nest,
             food_remaining,
-            proportion_explored
+            proportion_explored,
+            num_ants: 0,
+            first_delivery_tick: None,
+            distance_travelled: 0.0,
+            pheromone_deposited: 0.0,
         }
     }
 }
@@ -63,20 +216,75 @@ impl SimulationResult {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::simulation::environment::DiffusionSettings;
+    use crate::simulation::map::load_map;
+    use crate::neural_network::mlp::default_activations;
     #[test]
     fn test_simulation_new() {
-        let decision_network: MLP = MLP::new(37, vec![16, 1]);
-        let simulation = Simulation::new(50, 0.99, 100, decision_network);
+        let decision_network: MLP = MLP::new(37, vec![16, 1], default_activations(2));
+        let simulation = Simulation::new(50, 0.99, 100, decision_network, 0);
         assert_eq!(simulation.environment.size, 50);
     }
 
     #[test]
     fn test_simulation_run() {
-        let decision_network: MLP = MLP::new(38, vec![16, 1]);
-        let mut simulation = Simulation::new(50, 0.99, 100, decision_network);
+        let decision_network: MLP = MLP::new(38, vec![16, 1], default_activations(2));
+        let mut simulation = Simulation::new(50, 0.99, 100, decision_network, 0);
         let _sim_result = simulation.run(10);
         // let fake_sim_result = SimulationResult::new(10, 0., 25.);
 
         // assert_eq!(sim_result.num_iters, fake_sim_result.num_iters);
     }
+
+    #[test]
+    fn test_simulation_from_map() {
+        let environment = load_map("maps/obstacle_course.txt", DiffusionSettings::new()).unwrap();
+        let decision_network: MLP = MLP::new(38, vec![16, 1], default_activations(2));
+        let mut simulation = Simulation::from_environment(environment, 20, &AntSettings::new(), decision_network, 0);
+        let sim_result = simulation.run(10);
+
+        assert_eq!(sim_result.num_iters, 10);
+    }
+
+    #[test]
+    fn test_simulation_from_config() {
+        let config = SimulationConfig::from_file("configs/maze.json").unwrap();
+        let mut simulation = Simulation::from_config(&config, config.build_network(config.seed), config.seed).unwrap();
+        let sim_result = simulation.run(10);
+
+        assert_eq!(simulation.environment.size, 21);
+        assert_eq!(sim_result.num_iters, 10);
+    }
+
+    #[test]
+    fn test_num_iters() {
+        let decision_network: MLP = MLP::new(38, vec![16, 1], default_activations(2));
+        let mut simulation = Simulation::new(20, 0.99, 5, decision_network, 0);
+
+        // Each run reports its own steps, finish reports the whole episode
+        assert_eq!(simulation.run(10).num_iters, 10);
+        assert_eq!(simulation.run(5).num_iters, 5);
+        simulation.step();
+        assert_eq!(simulation.finish().num_iters, 16);
+    }
+
+    #[test]
+    fn test_simulation_is_reproducible() {
+        let decision_network: MLP = MLP::new(38, vec![16, 1], default_activations(2));
+        let mut simulation_1 = Simulation::new(50, 0.99, 20, decision_network.clone(), 1234);
+        let mut simulation_2 = Simulation::new(50, 0.99, 20, decision_network, 1234);
+        for _ in 0..100 {
+            simulation_1.step();
+            simulation_2.step();
+            for (row_1, row_2) in simulation_1.environment.grid.iter().zip(simulation_2.environment.grid.iter()) {
+                for (cell_1, cell_2) in row_1.iter().zip(row_2.iter()) {
+                    assert_eq!(cell_1.visited, cell_2.visited);
+                    assert_eq!(cell_1.nest_pheromone_concentration.to_bits(), cell_2.nest_pheromone_concentration.to_bits());
+                    assert_eq!(cell_1.food_pheromone_concentration.to_bits(), cell_2.food_pheromone_concentration.to_bits());
+                }
+            }
+        }
+
+        assert_eq!(simulation_1.run(100), simulation_2.run(100));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,133 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::file_format::{decode_body, encode_body, FileError, FileFormat};
+use crate::simulation::colony::Colony;
+use crate::simulation::environment::Environment;
+use crate::simulation::metrics::MetricsRecorder;
+use crate::simulation::simulation::Simulation;
+use crate::simulation::utils::SimulationRng;
+
+
+// Snapshot files have a SnapshotHeader, and the SimulationSnapshot as their body
+const SNAPSHOT_FILE: FileFormat = FileFormat {
+    name: "snapshot",
+    magic: b"ANTSNAPSHOT\n",
+    version: 1,
+};
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SnapshotHeader {
+    format_version: u32,
+    tick: usize,
+    arena_size: usize,
+    num_ants: usize,
+}
+
+
+// Everything a Simulation holds apart from its observers, which are left to whoever restores it.
+// Restoring a snapshot and stepping it gives exactly the ticks the original would have, so a
+// snapshot can be restored several times to branch an experiment.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SimulationSnapshot {
+    pub environment: Environment,
+    pub colony: Colony,
+    pub rng: SimulationRng,
+    pub tick: usize,
+    pub first_delivery_tick: Option<usize>,
+    pub metrics: Option<MetricsRecorder>,
+}
+
+
+impl SimulationSnapshot {
+    // Catches files that would otherwise panic on the first step
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let size = self.environment.size;
+        if self.environment.grid.len() != size || self.environment.grid.iter().any(|row| row.len() != size) {
+            return Err(format!("the grid isn't {} by {} cells", size, size))
+        }
+        if self.colony.ants().iter().any(|ant| ant.grid_location[0] >= size || ant.grid_location[1] >= size) {
+            return Err("an ant is outside the grid".to_string())
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Simulation {
+    pub fn snapshot(&self) -> SimulationSnapshot {
+        SimulationSnapshot {
+            environment: self.environment.clone(),
+            colony: self.colony.clone(),
+            rng: self.rng.clone(),
+            tick: self.tick,
+            first_delivery_tick: self.first_delivery_tick,
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    pub fn from_snapshot(snapshot: SimulationSnapshot) -> Simulation {
+        let mut simulation = Simulation::from_parts(snapshot.environment, snapshot.colony, snapshot.rng);
+        simulation.tick = snapshot.tick;
+        simulation.first_delivery_tick = snapshot.first_delivery_tick;
+        simulation.metrics = snapshot.metrics;
+
+        simulation
+    }
+
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), FileError> {
+        let header = SnapshotHeader {
+            format_version: SNAPSHOT_FILE.version,
+            tick: self.tick,
+            arena_size: self.environment.size,
+            num_ants: self.colony.ants().len(),
+        };
+
+        SNAPSHOT_FILE.write(path, &header, &encode_body(&self.snapshot())?)
+    }
+
+    // The restored simulation has no observers
+    pub fn load_snapshot<P: AsRef<Path>>(path: P) -> Result<Simulation, FileError> {
+        let (_, body): (SnapshotHeader, _) = SNAPSHOT_FILE.read(path)?;
+        let snapshot: SimulationSnapshot = decode_body(&body)?;
+        snapshot.validate().map_err(FileError::Format)?;
+
+        Ok(Simulation::from_snapshot(snapshot))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SimulationConfig;
+    #[test]
+    fn test_snapshot_round_trip() {
+        let config = SimulationConfig::from_file("configs/maze.json").unwrap();
+        let mut simulation = Simulation::from_config(&config, config.build_network(config.seed), 5).unwrap();
+        simulation.record_metrics();
+        simulation.run(50);
+
+        let path = std::env::temp_dir().join("test_snapshot_round_trip.snapshot");
+        simulation.save_snapshot(&path).unwrap();
+        let mut restored = Simulation::load_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut branch = Simulation::from_snapshot(restored.snapshot());
+        assert_eq!(restored.tick, 50);
+        assert_eq!(restored.metrics.as_ref().unwrap().ticks().len(), 50);
+
+        // The random number generator carries on where it was, so all three stay in step
+        let result = simulation.run(100);
+        assert_eq!(restored.run(100), result);
+        assert_eq!(branch.run(100), result);
+        assert_eq!(restored.metrics, simulation.metrics);
+        assert_eq!(restored.colony.directions(), simulation.colony.directions());
+
+        std::fs::write(&path, b"ANTMLP\n{}").unwrap();
+        assert!(matches!(Simulation::load_snapshot(&path), Err(FileError::Format(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt::Write;
+
+use image::Rgba;
+use piston::input::Key;
+
+use crate::simulation::colony::Colony;
+use crate::simulation::environment::Environment;
+use crate::simulation::render::blend;
+use crate::simulation::simulation::Simulation;
+use crate::simulation::world_view::{WorldView, WorldViewSettings};
+
+
+// The upper half block, coloured with the top cell as foreground and the bottom cell as background
+const HALF_BLOCK: char = '▀';
+const RESET: &str = "\x1b[0m";
+
+
+// Draws the same layers as WorldView with 24-bit ANSI colours, for watching a simulation in a
+// terminal over SSH. Each character is one cell wide and two cells tall, so line k of the output
+// shows grid rows y = 2k and 2k + 1. Ants fill the cell they are in, since their heading can't
+// be drawn at this size.
+pub struct TerminalView {
+    pub world_view: WorldView,
+}
+
+
+impl TerminalView {
+    pub fn new(settings: WorldViewSettings) -> TerminalView {
+        TerminalView {
+            world_view: WorldView::new(settings),
+        }
+    }
+
+    pub fn key_pressed(&mut self, key: Key) {
+        self.world_view.key_pressed(key);
+    }
+
+    fn cell_colors(&self, environment: &Environment, colony: &Colony) -> Vec<[u8; 3]> {
+        let settings = &self.world_view.settings;
+        let size = environment.size;
+        let mut colors = vec![[0; 3]; size * size];
+        for (i, grid_row) in environment.grid.iter().enumerate() {
+            for (j, cell) in grid_row.iter().enumerate() {
+                // The window clears to black
+                let mut pixel = Rgba([0, 0, 0, 255]);
+                self.world_view.for_each_cell_color(cell, |color| blend(&mut pixel, color));
+                colors[j * size + i] = [pixel[0], pixel[1], pixel[2]];
+            }
+        }
+        if settings.show_ants {
+            // Ants carrying food are drawn last so they show up in crowded cells
+            let mut ants: Vec<_> = colony.ants().iter().collect();
+            ants.sort_by_key(|ant| ant.has_food);
+            for ant in ants {
+                let [i, j] = ant.grid_location;
+                let color = if ant.has_food { settings.ant_with_food_color } else { settings.ant_color };
+                let mut pixel = Rgba([0, 0, 0, 255]);
+                blend(&mut pixel, color);
+                colors[j * size + i] = [pixel[0], pixel[1], pixel[2]];
+            }
+        }
+
+        colors
+    }
+
+    // One string per line of the terminal, each ending by resetting the colours
+    pub fn render(&self, environment: &Environment, colony: &Colony) -> Vec<String> {
+        let size = environment.size;
+        let colors = self.cell_colors(environment, colony);
+        let mut lines = Vec::new();
+        for top in (0..size).step_by(2) {
+            let mut line = String::new();
+            for x in 0..size {
+                let [r, g, b] = colors[top * size + x];
+                // An odd sized grid leaves the last line's bottom half black
+                let [br, bg, bb] = if top + 1 < size { colors[(top + 1) * size + x] } else { [0; 3] };
+                write!(line, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m{}", r, g, b, br, bg, bb, HALF_BLOCK).unwrap();
+            }
+            line.push_str(RESET);
+            lines.push(line);
+        }
+
+        lines
+    }
+}
+
+
+pub fn status_line(simulation: &Simulation, paused: bool, ups: u64) -> String {
+    let ants_carrying_food = simulation.colony.ants().iter().filter(|ant| ant.has_food).count();
+
+    format!(
+        "Tick {}  Food returned {:.1}  Ants carrying food {}/{}  {} ({} ups)",
+        simulation.tick,
+        simulation.environment.food_returned_to_nest,
+        ants_carrying_food,
+        simulation.colony.ants().len(),
+        if paused { "Paused" } else { "Running" },
+        ups,
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neural_network::mlp::{default_activations, MLP};
+    #[test]
+    fn test_terminal_view() {
+        let decision_network: MLP = MLP::new(38, vec![16, 1], default_activations(2));
+        let mut simulation = Simulation::new(15, 0.99, 10, decision_network, 0);
+        simulation.run(5);
+        let mut view = TerminalView::new(WorldViewSettings::new());
+        let lines = view.render(&simulation.environment, &simulation.colony);
+
+        assert_eq!(lines.len(), 8);
+        for line in lines.iter() {
+            assert_eq!(line.chars().filter(|c| *c == HALF_BLOCK).count(), 15);
+            assert!(line.ends_with(RESET));
+        }
+        // The top left cells are wall, drawn opaque grey
+        assert!(lines[0].starts_with("\x1b[38;2;128;128;128m\x1b[48;2;128;128;128m▀"));
+        // The bottom half of the last line is past the grid
+        assert!(lines[7].contains("\x1b[48;2;0;0;0m"));
+
+        view.key_pressed(Key::D4);
+        assert!(!view.world_view.settings.show_ants);
+        assert_ne!(view.render(&simulation.environment, &simulation.colony), lines);
+
+        let status = status_line(&simulation, true, 100);
+        assert!(status.starts_with("Tick 5 "));
+        assert!(status.contains("/10"));
+    }
+}
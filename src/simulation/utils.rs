@@ -1,5 +1,11 @@
 use ndarray::{Array, Dim};
-use rand;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+
+// Every stochastic part of a simulation draws from a single generator of this type, so a run
+// can be reproduced exactly from its seed
+pub type SimulationRng = ChaCha8Rng;
 
 
 pub fn normalize_array(array: Array<f32, Dim<[usize; 1]>>) -> Array<f32, Dim<[usize; 1]>> {
@@ -12,8 +18,8 @@ pub fn normalize_array(array: Array<f32, Dim<[usize; 1]>>) -> Array<f32, Dim<[us
 }
 
 
-pub fn random_unit_vector() -> Array<f32, Dim<[usize; 1]>> {
-    let v = Array::from(vec![rand::random::<f32>(), rand::random::<f32>()]);
+pub fn random_unit_vector<R: Rng>(rng: &mut R) -> Array<f32, Dim<[usize; 1]>> {
+    let v = Array::from(vec![rng.gen::<f32>(), rng.gen::<f32>()]);
     
     normalize_array(v)
 }
@@ -37,11 +43,11 @@ pub fn get_direction_from_coords(
 }
 
 
-pub fn random_rotation(array: &Array<f32, Dim<[usize; 1]>>, range: f32) -> Array<f32, Dim<[usize; 1]>> {
+pub fn random_rotation<R: Rng>(array: &Array<f32, Dim<[usize; 1]>>, range: f32, rng: &mut R) -> Array<f32, Dim<[usize; 1]>> {
     // Take a value, in radians, between 0 and 1, subtract 0.5 so its now in the range
     // [-0.5, 0.5), divide by 2. So the new direction is +/ 0.25 radians
     // from the direction of the input vector
-    let mut random_radians = rand::random::<f32>();
+    let mut random_radians = rng.gen::<f32>();
     random_radians -= 0.5;
     random_radians *= range;
 
@@ -54,11 +60,21 @@ pub fn random_rotation(array: &Array<f32, Dim<[usize; 1]>>, range: f32) -> Array
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::SeedableRng;
     #[test]
     fn test_random_unit_vector() {
-        let r1 = random_unit_vector();
-        let r2 = random_unit_vector();
+        let mut rng = SimulationRng::seed_from_u64(0);
+        let r1 = random_unit_vector(&mut rng);
+        let r2 = random_unit_vector(&mut rng);
 
         assert_ne!(r1, r2);
     }
+
+    #[test]
+    fn test_random_unit_vector_is_seedable() {
+        let r1 = random_unit_vector(&mut SimulationRng::seed_from_u64(42));
+        let r2 = random_unit_vector(&mut SimulationRng::seed_from_u64(42));
+
+        assert_eq!(r1, r2);
+    }
 }
\ No newline at end of file
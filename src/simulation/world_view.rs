@@ -1,13 +1,23 @@
 use graphics::types::Color;
 use graphics::{Context, Graphics};
+use piston::input::{Button, GenericEvent, Key};
 
-use crate::simulation::environment::Environment;
-// use crate::simulation::colony::Colony;
+use crate::simulation::environment::{Cell, Environment};
+use crate::simulation::colony::{Ant, Colony};
 
 
 pub struct WorldViewSettings {
     pub ant_color: Color,
-    pub pixel_size: usize
+    pub ant_with_food_color: Color,
+    // Length of the triangle drawn for each ant, in cells
+    pub ant_size: f64,
+    pub pixel_size: usize,
+    // Layers that can be toggled with the number keys 1 to 5
+    pub show_nest_pheromone: bool,
+    pub show_food_pheromone: bool,
+    pub show_food: bool,
+    pub show_ants: bool,
+    pub show_visited: bool,
 }
 
 
@@ -20,7 +30,14 @@ impl WorldViewSettings {
     pub fn new() -> WorldViewSettings {
         WorldViewSettings {
             ant_color: [1.0, 0.0, 0.0, 1.0],
+            ant_with_food_color: [1.0, 1.0, 0.0, 1.0],
+            ant_size: 1.5,
             pixel_size: 4,
+            show_nest_pheromone: true,
+            show_food_pheromone: true,
+            show_food: true,
+            show_ants: true,
+            show_visited: false,
         }
     }
 }
@@ -33,9 +50,50 @@ impl WorldView {
         }
     }
 
+    pub fn event<E: GenericEvent>(&mut self, e: &E) {
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            self.key_pressed(key);
+        }
+    }
+
+    // The number keys toggle the layers
+    pub fn key_pressed(&mut self, key: Key) {
+        let settings = &mut self.settings;
+        match key {
+            Key::D1 => settings.show_nest_pheromone = !settings.show_nest_pheromone,
+            Key::D2 => settings.show_food_pheromone = !settings.show_food_pheromone,
+            Key::D3 => settings.show_food = !settings.show_food,
+            Key::D4 => settings.show_ants = !settings.show_ants,
+            Key::D5 => settings.show_visited = !settings.show_visited,
+            _ => {},
+        }
+    }
+
+    // Calls draw with each colour drawn over a cell, bottom first, for the layers that are
+    // switched on. It's called for every cell on every frame, so nothing is collected.
+    pub fn for_each_cell_color<F: FnMut(Color)>(&self, cell: &Cell, mut draw: F) {
+        let settings = &self.settings;
+        if !cell.is_traversable {
+            draw([0.5, 0.5, 0.5, 1.0]);
+        } else if cell.is_nest {
+            draw([0.0, 0.0, 1.0, 0.5]);
+        } else if cell.food_amount > 0.0 && settings.show_food {
+            draw([0.0, 1.0, 0.0, cell.food_amount as f32]);
+        } else {
+            if settings.show_visited && cell.visited {
+                draw([1.0, 1.0, 1.0, 0.15]);
+            }
+            if settings.show_nest_pheromone {
+                draw([1.0, 0.0, 0.0, cell.nest_pheromone_concentration as f32]);
+            }
+            if settings.show_food_pheromone {
+                draw([0.0, 0.8, 0.1, cell.food_pheromone_concentration as f32]);
+            }
+        }
+    }
+
     fn draw_environment<G: Graphics>(&self, environment: &Environment, c: &Context, g: &mut G) {
         use graphics::{Rectangle, rectangle};
-        let nest_cell = Rectangle::new([0.0, 0.0, 1.0, 0.5]);
 
         for (i, grid_row) in environment.grid.iter().enumerate() {
             for (j, cell) in grid_row.iter().enumerate() {
@@ -44,41 +102,76 @@ impl WorldView {
                     (j * self.settings.pixel_size) as f64, 
                     self.settings.pixel_size as f64
                 );
-                if cell.is_nest {
-                    nest_cell.draw(square, &c.draw_state, c.transform, g);
-                } else if cell.food_amount > 0.0 {
-                    Rectangle::new([0.0, 1.0, 0.0, cell.food_amount as f32])
-                        .draw(square, &c.draw_state, c.transform, g);
-                } else {
-                    Rectangle::new([1.0, 0.0, 0.0, cell.nest_pheromone_concentration as f32])
-                        .draw(square, &c.draw_state, c.transform, g);
-                    Rectangle::new([0.0, 0.8, 0.1, cell.food_pheromone_concentration as f32])
-                        .draw(square, &c.draw_state, c.transform, g);
-                }
+                self.for_each_cell_color(cell, |color| {
+                    Rectangle::new(color).draw(square, &c.draw_state, c.transform, g);
+                });
             }
         }
     }
 
-    // fn draw_colony<G: Graphics>(&self, colony: &Colony, c: &Context, g: &mut G) {
-    //     use graphics::{Rectangle, rectangle};
-    //     let ref settings = self.settings;
-    //     let ant_vis = Rectangle::new(self.settings.ant_color);
-
-    //     for ant in colony.ants.iter() {
-    //         let square = rectangle::square(
-    //             (ant.grid_location[0] * settings.pixel_size) as f64, 
-    //             (ant.grid_location[1] * settings.pixel_size) as f64, 
-    //             self.settings.pixel_size as f64
-    //         );
+    pub fn ant_triangle(&self, ant: &Ant) -> [[f64; 2]; 3] {
+        // An isosceles triangle centred on the ant with its tip pointing along the ant's direction
+        let pixel_size = self.settings.pixel_size as f64;
+        let length = self.settings.ant_size * pixel_size;
+        let centre = [ant.coordinates[[0]] as f64 * pixel_size, ant.coordinates[[1]] as f64 * pixel_size];
+        let direction = [ant.direction[[0]] as f64, ant.direction[[1]] as f64];
+        let perpendicular = [-direction[1], direction[0]];
+
+        [
+            [centre[0] + direction[0] * length / 2., centre[1] + direction[1] * length / 2.],
+            [
+                centre[0] - direction[0] * length / 2. + perpendicular[0] * length / 3.,
+                centre[1] - direction[1] * length / 2. + perpendicular[1] * length / 3.,
+            ],
+            [
+                centre[0] - direction[0] * length / 2. - perpendicular[0] * length / 3.,
+                centre[1] - direction[1] * length / 2. - perpendicular[1] * length / 3.,
+            ],
+        ]
+    }
 
-    //         ant_vis.draw(square, &c.draw_state, c.transform, g);
-    //     }
-    // }
+    fn draw_colony<G: Graphics>(&self, colony: &Colony, c: &Context, g: &mut G) {
+        use graphics::Polygon;
+        let ant_vis = Polygon::new(self.settings.ant_color);
+        let ant_with_food_vis = Polygon::new(self.settings.ant_with_food_color);
+
+        for ant in colony.ants().iter() {
+            let triangle = self.ant_triangle(ant);
+            if ant.has_food {
+                ant_with_food_vis.draw(&triangle, &c.draw_state, c.transform, g);
+            } else {
+                ant_vis.draw(&triangle, &c.draw_state, c.transform, g);
+            }
+        }
+    }
 
-    pub fn draw<G: Graphics>(&self, environment: &Environment, c: &Context, g: &mut G) {
+    pub fn draw<G: Graphics>(&self, environment: &Environment, colony: &Colony, c: &Context, g: &mut G) {
         self.draw_environment(environment, c, g);
-        // self.draw_colony(colony, c, g);
+        if self.settings.show_ants {
+            self.draw_colony(colony, c, g);
+        }
+    }
+}
 
-        
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use ndarray::Array;
+    use crate::simulation::utils::SimulationRng;
+    #[test]
+    fn test_ant_triangle_points_along_direction() {
+        let world_view = WorldView::new(WorldViewSettings::new());
+        let mut ant = Ant::new(&mut SimulationRng::seed_from_u64(0));
+        ant.coordinates = Array::from(vec![10.0, 20.0]);
+        ant.direction = Array::from(vec![1.0, 0.0]);
+        let triangle = world_view.ant_triangle(&ant);
+
+        // Pixel size 4 and ant size 1.5 cells, so the tip is 3 pixels ahead of the centre
+        assert_eq!(triangle[0], [43.0, 80.0]);
+        assert_eq!(triangle[1][0], 37.0);
+        assert_eq!(triangle[2][0], 37.0);
+        assert_eq!(triangle[1][1] - 80.0, 80.0 - triangle[2][1]);
     }
-}
\ No newline at end of file
+}
@@ -1,69 +1,197 @@
-extern crate rust_es;
 extern crate lib;
 extern crate openblas_src;
 
+use std::time::Instant;
+
 use ndarray::{Array, Dim};
 use ndarray_rand::RandomExt;
 use ndarray_rand::rand_distr::Uniform;
 use ndarray_npy::write_npy;
+use rand::SeedableRng;
 
-use rust_es::nes::NES;
-use rust_es::objective::Objective;
-use rust_es::utils::random_gaussian_vector;
-use lib::simulation::simulation::Simulation;
+use lib::config::SimulationConfig;
 use lib::neural_network::mlp::MLP;
+use lib::optimization::{Objective, Optimizer};
+use lib::simulation::utils::SimulationRng;
+use lib::training::checkpoint::Checkpoint;
+use lib::training::evaluator::Evaluator;
+use lib::training::log::{GenerationStats, TrainingLog};
 
 
-const NUM_ANTS: usize = 20;
-const ARENA_SIZE: usize = 75;
-const DIFFUSION_RATE: f64 = 0.999;
-//const NUM_PERCEPTION_SAMPLES: usize = 10;
-const NUM_STEPS_PER_SIM: usize = 500;
-const INPUT_DIMENSION: usize = 38;
-
 #[derive(Clone)]
 struct SimulationWrapper {
+    config: SimulationConfig,
+    evaluator: Evaluator,
 }
 
 
 impl SimulationWrapper {
+    fn new(config: SimulationConfig) -> SimulationWrapper {
+        let evaluator = Evaluator::new(config.clone()).unwrap_or_else(|e| exit_with_error(e.to_string()));
 
+        SimulationWrapper { config, evaluator }
+    }
 }
 
 
 impl Objective for SimulationWrapper {
     fn call(&self, x: &Array<f32, Dim<[usize; 1]>>) -> f32 {
-        let x = x.clone();
-        let num_runs = 10;
-        let mut total_reward = 0 as f64;
-        for _ in 0..num_runs {
-            let mlp = MLP::from_flattened_weights(INPUT_DIMENSION, vec![16, 2], x.clone());
-            let mut simulation = Simulation::new(ARENA_SIZE, DIFFUSION_RATE, NUM_ANTS, mlp);
-            let simulation_result = simulation.run(NUM_STEPS_PER_SIM);
-
-            let reward = -simulation_result.food_returned_to_nest - simulation_result.proportion_explored + simulation_result.food_remaining / 10.;
-            total_reward += reward;
+        // The optimizers minimize, so the objective is the negated reward
+        -self.evaluator.evaluate(x) as f32
+    }
+
+    fn call_population(&self, population: &[Array<f32, Dim<[usize; 1]>>]) -> Vec<f32> {
+        self.evaluator.evaluate_population(population).iter().map(|reward| -*reward as f32).collect()
+    }
+}
+
+
+const USAGE: &str = "Usage: train_agents [--config path | --resume checkpoint] [--workers n]";
+
+
+fn exit_with_error(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+
+fn new_checkpoint(config: SimulationConfig) -> Checkpoint {
+    let distribution = Uniform::new(-0.01, 0.01);
+    let mut rng = SimulationRng::seed_from_u64(config.seed);
+    let initial_mean = Array::random_using(config.num_parameters(), distribution, &mut rng);
+    let optimizer = config.build_optimizer(initial_mean);
+
+    Checkpoint::new(config, optimizer)
+}
+
+
+// Also returns whether the checkpoint was resumed from a file rather than started afresh
+fn load_checkpoint() -> (Checkpoint, bool) {
+    let args: Vec<String> = std::env::args().collect();
+    let mut config: Option<SimulationConfig> = None;
+    let mut resume_path: Option<String> = None;
+    let mut num_workers: Option<usize> = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--config" if i + 1 < args.len() => {
+                config = Some(SimulationConfig::from_file(&args[i + 1]).unwrap_or_else(|e| {
+                    exit_with_error(format!("{}: {}", args[i + 1], e))
+                }));
+                i += 2;
+            },
+            "--resume" if i + 1 < args.len() => {
+                resume_path = Some(args[i + 1].clone());
+                i += 2;
+            },
+            "--workers" if i + 1 < args.len() => {
+                num_workers = Some(args[i + 1].parse().unwrap_or_else(|_| {
+                    exit_with_error(format!("--workers expects a number of threads, got {:?}", args[i + 1]))
+                }));
+                i += 2;
+            },
+            arg => exit_with_error(format!("Unrecognised argument {:?}. {}", arg, USAGE)),
         }
-        
+    }
 
-        total_reward as f32/ num_runs as f32
+    let resume = resume_path.is_some();
+    let mut checkpoint = match (config, resume_path) {
+        (Some(_), Some(_)) => exit_with_error(format!(
+            "A checkpoint carries the config it was trained with, so --config can't be used with --resume. {}", USAGE
+        )),
+        (config, None) => new_checkpoint(config.unwrap_or_else(SimulationConfig::new)),
+        (None, Some(path)) => {
+            let checkpoint = Checkpoint::load(&path).unwrap_or_else(|e| exit_with_error(format!("{}: {}", path, e)));
+            println!("Resuming from generation {} of {}", checkpoint.generation(), path);
+            checkpoint
+        },
+    };
+    // The command line wins over the config file so the same config can be run on any machine,
+    // the number of workers never changes the results
+    if let Some(num_workers) = num_workers {
+        checkpoint.config.training.num_workers = num_workers;
+    }
+
+    (checkpoint, resume)
+}
+
+
+fn open_log(log_path: &str, resume: bool) -> Option<TrainingLog> {
+    if log_path.is_empty() {
+        return None
+    }
+
+    Some(TrainingLog::create(log_path, resume).unwrap_or_else(|e| exit_with_error(format!("{}: {}", log_path, e))))
+}
+
+
+fn save_checkpoint(checkpoint: &Checkpoint) {
+    let path = &checkpoint.config.training.checkpoint_path;
+    match checkpoint.save(path) {
+        Ok(()) => println!("Saved checkpoint at generation {} to {}", checkpoint.generation(), path),
+        // Losing a checkpoint shouldn't end a long run, the next one may well succeed
+        Err(e) => eprintln!("{}: {}", path, e),
     }
 }
 
 
 fn main() {
-    let distribution = Uniform::new(-0.01, 0.01);
-    let hidden_sizes = vec![16, 16, 2];
-    let num_parameters = INPUT_DIMENSION * hidden_sizes[0] * hidden_sizes[1] * hidden_sizes[2];
-    let mu = Array::random(num_parameters, distribution);
-    let sigma = Array::ones(num_parameters) / 10.;
-    let callable = SimulationWrapper {};
-    let mut nes = NES::new(callable.clone(), mu, sigma, 64, 0.01, 0.001, true);
-    
-    for i in 0..500 {
-        nes.step();
-        println!("Step {} complete", i);
+    let (mut checkpoint, resume) = load_checkpoint();
+    let training = checkpoint.config.training.clone();
+    let callable = SimulationWrapper::new(checkpoint.config.clone());
+    println!("Training {} parameters with {:?}, evaluating with {} workers",
+        callable.config.num_parameters(), checkpoint.optimizer.kind(), callable.evaluator.num_workers());
+
+    let mut log = open_log(&training.log_path, resume);
+
+    let mut generations_since_checkpoint = 0;
+    while checkpoint.generation() < training.num_generations {
+        let start_time = Instant::now();
+        let generation = checkpoint.generation();
+        let population = checkpoint.optimizer.ask();
+        // Simulated directly rather than through Objective::call_population so the individual
+        // results are available for the log
+        let results = callable.evaluator.simulate_population(&population);
+        let rewards: Vec<f64> = results.iter().map(|candidate_results| callable.evaluator.mean_reward(candidate_results)).collect();
+        let fitnesses: Vec<f32> = rewards.iter().map(|reward| -*reward as f32).collect();
+        checkpoint.optimizer.tell(&fitnesses);
+        checkpoint.record(&population, &rewards, generation);
+
+        let stats = GenerationStats::new(
+            generation, &rewards, &results, checkpoint.optimizer.sigma_norm(), start_time.elapsed().as_secs_f64()
+        );
+        println!(
+            "Step {} complete in {:.1}s, fitness mean {:.4} max {:.4} min {:.4} std {:.4}, sigma norm {:.4}",
+            generation, stats.wall_time_seconds, stats.fitness_mean, stats.fitness_max, stats.fitness_min,
+            stats.fitness_std, stats.sigma_norm
+        );
+        if let Some(log) = log.as_mut() {
+            if let Err(e) = log.write(&stats) {
+                eprintln!("{}: {}", training.log_path, e);
+            }
+        }
+
+        generations_since_checkpoint += 1;
+        if training.checkpoint_interval > 0 && generations_since_checkpoint == training.checkpoint_interval {
+            save_checkpoint(&checkpoint);
+            generations_since_checkpoint = 0;
+        }
+    }
+    if training.checkpoint_interval > 0 && generations_since_checkpoint > 0 {
+        save_checkpoint(&checkpoint);
     }
 
-    write_npy("trial_4.npy", &nes.mu).unwrap();
-}
\ No newline at end of file
+    let weights = checkpoint.optimizer.mean().clone();
+    if training.output_path.ends_with(".npy") {
+        write_npy(&training.output_path, &weights).unwrap();
+    } else {
+        let config = &checkpoint.config;
+        let mut metadata = config.model_metadata();
+        metadata.training.insert("generations".to_string(), training.num_generations.to_string());
+        if let Some(best) = &checkpoint.best {
+            metadata.training.insert("best_reward".to_string(), best.reward.to_string());
+        }
+        let mlp = MLP::from_flattened_weights(config.input_dimension(), config.network.hidden_sizes.clone(), config.network.activations(), weights);
+        mlp.save(&training.output_path, &metadata).unwrap();
+    }
+}
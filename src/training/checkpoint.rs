@@ -0,0 +1,192 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use ndarray::{Array, Dim};
+use serde::{Deserialize, Serialize};
+
+use crate::config::SimulationConfig;
+use crate::optimization::{EvolutionStrategy, Optimizer};
+
+
+const CHECKPOINT_VERSION: u32 = 1;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BestCandidate {
+    pub weights: Array<f32, Dim<[usize; 1]>>,
+    pub reward: f64,
+    pub generation: usize,
+}
+
+
+// Everything needed to carry on training exactly where it stopped. The optimizer includes its
+// RNG, and the evaluator's seeds only depend on the config, so a resumed run produces the same
+// generations as one that was never interrupted.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub config: SimulationConfig,
+    pub optimizer: EvolutionStrategy,
+    pub best: Option<BestCandidate>,
+}
+
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(std::io::Error),
+    Format(String),
+}
+
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "could not access checkpoint file: {}", e),
+            CheckpointError::Format(e) => write!(f, "invalid checkpoint file: {}", e),
+        }
+    }
+}
+
+
+impl std::error::Error for CheckpointError {}
+
+
+impl From<std::io::Error> for CheckpointError {
+    fn from(e: std::io::Error) -> CheckpointError {
+        CheckpointError::Io(e)
+    }
+}
+
+
+impl Checkpoint {
+    pub fn new(config: SimulationConfig, optimizer: EvolutionStrategy) -> Checkpoint {
+        Checkpoint {
+            version: CHECKPOINT_VERSION,
+            config,
+            optimizer,
+            best: None,
+        }
+    }
+
+    pub fn generation(&self) -> usize {
+        self.optimizer.generation()
+    }
+
+    // Keeps the best candidate seen so far, rewards are higher for better candidates
+    pub fn record(&mut self, population: &[Array<f32, Dim<[usize; 1]>>], rewards: &[f64], generation: usize) {
+        for (weights, reward) in population.iter().zip(rewards.iter()) {
+            let is_better = match &self.best {
+                Some(best) => *reward > best.reward,
+                None => !reward.is_nan(),
+            };
+            if is_better {
+                self.best = Some(BestCandidate { weights: weights.clone(), reward: *reward, generation });
+            }
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), CheckpointError> {
+        // Written next to the target and renamed over it, so being killed mid-write can't
+        // destroy the previous checkpoint
+        let path = path.as_ref();
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+        let text = serde_json::to_string(self).map_err(|e| CheckpointError::Format(e.to_string()))?;
+        fs::write(&temporary_path, text)?;
+        fs::rename(&temporary_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Checkpoint, CheckpointError> {
+        let text = fs::read_to_string(path)?;
+        let checkpoint: Checkpoint = serde_json::from_str(&text).map_err(|e| CheckpointError::Format(e.to_string()))?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(CheckpointError::Format(format!("unsupported checkpoint version {}", checkpoint.version)))
+        }
+        checkpoint.config.validate().map_err(|e| CheckpointError::Format(e.to_string()))?;
+        if checkpoint.optimizer.mean().len() != checkpoint.config.num_parameters() {
+            return Err(CheckpointError::Format(format!(
+                "optimizer has {} parameters but the config's network needs {}",
+                checkpoint.optimizer.mean().len(), checkpoint.config.num_parameters()
+            )))
+        }
+
+        Ok(checkpoint)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimization::OptimizerKind;
+
+    fn train(checkpoint: &mut Checkpoint, generations: usize) {
+        // A stand in for the simulations, the reward peaks when every weight is 0.25
+        while checkpoint.generation() < generations {
+            let generation = checkpoint.generation();
+            let population = checkpoint.optimizer.ask();
+            let rewards: Vec<f64> = population.iter().map(|x| -x.mapv(|w| (w - 0.25) * (w - 0.25)).sum() as f64).collect();
+            let fitnesses: Vec<f32> = rewards.iter().map(|reward| -*reward as f32).collect();
+            checkpoint.optimizer.tell(&fitnesses);
+            checkpoint.record(&population, &rewards, generation);
+        }
+    }
+
+    #[test]
+    fn test_resume_matches_uninterrupted_training() {
+        let path = std::env::temp_dir().join("ant_sim_test_resume_checkpoint.json");
+        for kind in [OptimizerKind::OpenAiEs, OptimizerKind::SeparableNes, OptimizerKind::CmaEs].iter() {
+            let mut config = SimulationConfig::new();
+            config.network.hidden_sizes = vec![2];
+            config.training.population_size = 8;
+            config.training.optimizer = *kind;
+            let initial_mean = Array::zeros(config.num_parameters());
+            let mut uninterrupted = Checkpoint::new(config.clone(), config.build_optimizer(initial_mean.clone()));
+            let mut interrupted = Checkpoint::new(config.clone(), config.build_optimizer(initial_mean));
+
+            train(&mut uninterrupted, 12);
+            train(&mut interrupted, 5);
+            interrupted.save(&path).unwrap();
+            let mut resumed = Checkpoint::load(&path).unwrap();
+            train(&mut resumed, 12);
+
+            assert_eq!(resumed.optimizer.mean(), uninterrupted.optimizer.mean());
+            assert_eq!(resumed.best, uninterrupted.best);
+            // Better than where the search started, with every weight at 0
+            assert!(resumed.best.unwrap().reward > -0.0625 * config.num_parameters() as f64);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_resumed_cma_es_asks_for_identical_candidates() {
+        // CMA-ES keeps a covariance matrix and step size in f64, the first state to drift if a
+        // float doesn't survive the trip through JSON
+        let path = std::env::temp_dir().join("ant_sim_test_resume_cma_es.json");
+        let mut config = SimulationConfig::new();
+        config.network.hidden_sizes = vec![3];
+        config.training.population_size = 10;
+        config.training.optimizer = OptimizerKind::CmaEs;
+        let mut checkpoint = Checkpoint::new(config.clone(), config.build_optimizer(Array::zeros(config.num_parameters())));
+        train(&mut checkpoint, 7);
+        checkpoint.save(&path).unwrap();
+        let mut resumed = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        for _ in 0..3 {
+            let expected = checkpoint.optimizer.ask();
+            let population = resumed.optimizer.ask();
+            for (candidate, expected_candidate) in population.iter().zip(expected.iter()) {
+                let bits: Vec<u32> = candidate.iter().map(|w| w.to_bits()).collect();
+                let expected_bits: Vec<u32> = expected_candidate.iter().map(|w| w.to_bits()).collect();
+                assert_eq!(bits, expected_bits);
+            }
+            let fitnesses: Vec<f32> = (0..population.len()).map(|i| i as f32).collect();
+            checkpoint.optimizer.tell(&fitnesses);
+            resumed.optimizer.tell(&fitnesses);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use ndarray::{Array, Dim};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::config::SimulationConfig;
+use crate::neural_network::mlp::MLP;
+use crate::simulation::environment::Environment;
+use crate::simulation::map::MapError;
+use crate::simulation::simulation::{Simulation, SimulationResult};
+use crate::training::reward::Reward;
+
+
+// Scores flattened network weights by the reward they earn over several simulation runs, with
+// the runs spread over a pool of worker threads. Run r of every candidate uses the seed
+// config.seed + r (common random numbers), so differences in reward come from the weights
+// rather than from luck, and a run's result doesn't depend on which worker ran it or when.
+// The environment is built once, every run starts from a copy of it.
+#[derive(Clone)]
+pub struct Evaluator {
+    config: SimulationConfig,
+    environment: Environment,
+    pool: Arc<ThreadPool>,
+}
+
+
+impl Evaluator {
+    pub fn new(config: SimulationConfig) -> Result<Evaluator, MapError> {
+        let environment = config.environment.build()?;
+        // A num_workers of 0 lets rayon use one thread per CPU core
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(config.training.num_workers)
+            .build()
+            .unwrap();
+
+        Ok(Evaluator {
+            config,
+            environment,
+            pool: Arc::new(pool),
+        })
+    }
+
+    pub fn num_workers(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    pub fn run_seed(&self, run: usize) -> u64 {
+        self.config.seed.wrapping_add(run as u64)
+    }
+
+    fn run(&self, weights: &Array<f32, Dim<[usize; 1]>>, run: usize) -> SimulationResult {
+        let network = MLP::from_flattened_weights(
+            self.config.input_dimension(),
+            self.config.network.hidden_sizes.clone(),
+            self.config.network.activations(),
+            weights.clone(),
+        );
+        let mut simulation = Simulation::from_environment(
+            self.environment.clone(), self.config.colony.num_ants, &self.config.ant, network, self.run_seed(run)
+        );
+
+        simulation.run(self.config.num_steps)
+    }
+
+    pub fn mean_reward(&self, results: &[SimulationResult]) -> f64 {
+        // Summed in run order so floating point rounding can't depend on scheduling
+        results.iter().map(|result| self.config.reward.reward(result)).sum::<f64>() / results.len() as f64
+    }
+
+    pub fn evaluate(&self, weights: &Array<f32, Dim<[usize; 1]>>) -> f64 {
+        let num_runs = self.config.training.runs_per_evaluation;
+        let results: Vec<SimulationResult> = self.pool.install(|| {
+            (0..num_runs).into_par_iter().map(|run| self.run(weights, run)).collect()
+        });
+
+        self.mean_reward(&results)
+    }
+
+    // The result of every run of every candidate, grouped by candidate
+    pub fn simulate_population(&self, population: &[Array<f32, Dim<[usize; 1]>>]) -> Vec<Vec<SimulationResult>> {
+        // Every (candidate, run) pair is its own job so small populations still fill the pool
+        let num_runs = self.config.training.runs_per_evaluation;
+        let results: Vec<SimulationResult> = self.pool.install(|| {
+            (0..population.len() * num_runs)
+                .into_par_iter()
+                .map(|job| self.run(&population[job / num_runs], job % num_runs))
+                .collect()
+        });
+
+        results.chunks(num_runs).map(|candidate_results| candidate_results.to_vec()).collect()
+    }
+
+    pub fn evaluate_population(&self, population: &[Array<f32, Dim<[usize; 1]>>]) -> Vec<f64> {
+        self.simulate_population(population).iter().map(|results| self.mean_reward(results)).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_evaluation_does_not_depend_on_workers() {
+        let mut config = SimulationConfig::new();
+        config.environment.arena_size = 20;
+        config.colony.num_ants = 5;
+        config.num_steps = 30;
+        config.training.runs_per_evaluation = 3;
+        let population: Vec<_> = (0..4).map(|seed| config.build_network(seed).flatten_weights()).collect();
+
+        config.training.num_workers = 1;
+        let serial_evaluator = Evaluator::new(config.clone()).unwrap();
+        config.training.num_workers = 4;
+        let parallel_evaluator = Evaluator::new(config).unwrap();
+        let serial_rewards = serial_evaluator.evaluate_population(&population);
+
+        assert_eq!(parallel_evaluator.num_workers(), 4);
+        assert_eq!(parallel_evaluator.evaluate_population(&population), serial_rewards);
+        for (weights, reward) in population.iter().zip(serial_rewards.iter()) {
+            assert_eq!(parallel_evaluator.evaluate(weights), *reward);
+        }
+    }
+
+    #[test]
+    fn test_map_is_only_read_once() {
+        let path = std::env::temp_dir().join("ant_sim_test_evaluator_map.txt");
+        std::fs::copy("maps/maze.txt", &path).unwrap();
+        let mut config = SimulationConfig::new();
+        config.environment.map = Some(path.to_str().unwrap().to_string());
+        config.colony.num_ants = 5;
+        config.num_steps = 30;
+        config.training.runs_per_evaluation = 2;
+        let network = config.build_network(0);
+        let expected: Vec<SimulationResult> = (0..2)
+            .map(|run| Simulation::from_config(&config, network.clone(), config.seed + run).unwrap().run(30))
+            .collect();
+        let evaluator = Evaluator::new(config).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(evaluator.evaluate(&network.flatten_weights()), evaluator.mean_reward(&expected));
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::simulation::SimulationResult;
+
+
+// One row of the training log. Fitness here is the reward each candidate earned, averaged over
+// its runs, so higher is better; the simulation result fields are averaged over every run of
+// every candidate in the generation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GenerationStats {
+    pub generation: usize,
+    pub fitness_mean: f64,
+    pub fitness_max: f64,
+    pub fitness_min: f64,
+    pub fitness_std: f64,
+    pub food_returned_mean: f64,
+    pub food_remaining_mean: f64,
+    pub proportion_explored_mean: f64,
+    pub sigma_norm: f32,
+    pub wall_time_seconds: f64,
+}
+
+
+const CSV_HEADER: &str = "generation,fitness_mean,fitness_max,fitness_min,fitness_std,\
+food_returned_mean,food_remaining_mean,proportion_explored_mean,sigma_norm,wall_time_seconds";
+
+
+impl GenerationStats {
+    pub fn new(
+        generation: usize,
+        fitnesses: &[f64],
+        results: &[Vec<SimulationResult>],
+        sigma_norm: f32,
+        wall_time_seconds: f64
+    ) -> GenerationStats {
+        let num_candidates = fitnesses.len() as f64;
+        let fitness_mean = fitnesses.iter().sum::<f64>() / num_candidates;
+        let fitness_variance = fitnesses.iter().map(|f| (f - fitness_mean).powi(2)).sum::<f64>() / num_candidates;
+        let all_results: Vec<&SimulationResult> = results.iter().flatten().collect();
+        let mean_of = |field: fn(&SimulationResult) -> f64| {
+            all_results.iter().map(|result| field(result)).sum::<f64>() / all_results.len() as f64
+        };
+
+        GenerationStats {
+            generation,
+            fitness_mean,
+            fitness_max: fitnesses.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            fitness_min: fitnesses.iter().cloned().fold(f64::INFINITY, f64::min),
+            fitness_std: fitness_variance.sqrt(),
+            food_returned_mean: mean_of(|result| result.food_returned_to_nest),
+            food_remaining_mean: mean_of(|result| result.food_remaining),
+            proportion_explored_mean: mean_of(|result| result.proportion_explored),
+            sigma_norm,
+            wall_time_seconds,
+        }
+    }
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{}",
+            self.generation, self.fitness_mean, self.fitness_max, self.fitness_min, self.fitness_std,
+            self.food_returned_mean, self.food_remaining_mean, self.proportion_explored_mean,
+            self.sigma_norm, self.wall_time_seconds
+        )
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Csv,
+    Jsonl,
+}
+
+
+// Writes one line per generation, flushed straight away so the log is complete up to the last
+// finished generation even if training is killed. A new run replaces any existing log, while a
+// resumed run appends to it so the file carries on; if training died between writing a row and
+// saving a checkpoint, the rows after the checkpoint appear twice and can be told apart by
+// position.
+pub struct TrainingLog {
+    writer: BufWriter<File>,
+    format: LogFormat,
+}
+
+
+impl TrainingLog {
+    pub fn create<P: AsRef<Path>>(path: P, resume: bool) -> io::Result<TrainingLog> {
+        // .csv files get a header row, anything else is written as JSON lines
+        let path = path.as_ref();
+        let format = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => LogFormat::Csv,
+            _ => LogFormat::Jsonl,
+        };
+        let file = if resume {
+            OpenOptions::new().create(true).append(true).open(path)?
+        } else {
+            File::create(path)?
+        };
+        let is_empty = file.metadata()?.len() == 0;
+        let mut log = TrainingLog { writer: BufWriter::new(file), format };
+        if format == LogFormat::Csv && is_empty {
+            writeln!(log.writer, "{}", CSV_HEADER)?;
+            log.writer.flush()?;
+        }
+
+        Ok(log)
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    pub fn write(&mut self, stats: &GenerationStats) -> io::Result<()> {
+        match self.format {
+            LogFormat::Csv => writeln!(self.writer, "{}", stats.csv_row())?,
+            LogFormat::Jsonl => writeln!(self.writer, "{}", serde_json::to_string(stats).unwrap())?,
+        }
+
+        self.writer.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    #[test]
+    fn test_write_csv_and_jsonl() {
+        let results = vec![
+            vec![SimulationResult::new(10, 1.0, 4.0, 0.25), SimulationResult::new(10, 3.0, 2.0, 0.5)],
+            vec![SimulationResult::new(10, 0.0, 6.0, 0.125), SimulationResult::new(10, 0.0, 6.0, 0.125)],
+        ];
+        let stats = GenerationStats::new(3, &[2.0, 0.0], &results, 1.5, 0.25);
+        assert_eq!(stats.fitness_mean, 1.0);
+        assert_eq!(stats.fitness_std, 1.0);
+        assert_eq!((stats.fitness_min, stats.fitness_max), (0.0, 2.0));
+        assert_eq!(stats.food_returned_mean, 1.0);
+        assert_eq!(stats.food_remaining_mean, 4.5);
+
+        let csv_path = std::env::temp_dir().join("ant_sim_test_training_log.csv");
+        let jsonl_path = std::env::temp_dir().join("ant_sim_test_training_log.jsonl");
+        for path in [&csv_path, &jsonl_path].iter() {
+            let _ = fs::remove_file(path);
+            // A new run replaces what an earlier one left behind
+            fs::write(path, "left over from an earlier run\n").unwrap();
+            TrainingLog::create(path, false).unwrap().write(&stats).unwrap();
+            // Resuming appends without repeating the header
+            TrainingLog::create(path, true).unwrap().write(&stats).unwrap();
+        }
+
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines[1], "3,1,2,0,1,1,4.5,0.25,1.5,0.25");
+        let jsonl = fs::read_to_string(&jsonl_path).unwrap();
+        for line in jsonl.lines() {
+            assert_eq!(serde_json::from_str::<GenerationStats>(line).unwrap(), stats);
+        }
+        fs::remove_file(&csv_path).unwrap();
+        fs::remove_file(&jsonl_path).unwrap();
+    }
+}
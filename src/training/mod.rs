@@ -0,0 +1,4 @@
+pub mod checkpoint;
+pub mod evaluator;
+pub mod log;
+pub mod reward;
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::simulation::SimulationResult;
+
+
+// Scores a finished simulation for training, higher is better
+pub trait Reward {
+    fn reward(&self, simulation_result: &SimulationResult) -> f64;
+}
+
+
+impl<F: Fn(&SimulationResult) -> f64> Reward for F {
+    fn reward(&self, simulation_result: &SimulationResult) -> f64 {
+        self(simulation_result)
+    }
+}
+
+
+// The building blocks of a reward. Each one measures a single thing about a run and is meant to
+// be weighted, with a negative weight for anything that should be discouraged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RewardTerm {
+    // Total food carried back to the nest
+    FoodReturned,
+    // Food carried back to the nest per tick
+    DeliveryRate,
+    // Food left in the arena at the end
+    FoodRemaining,
+    // Fraction of the arena's cells visited by at least one ant
+    ProportionExplored,
+    // Ticks until the first food reached the nest as a fraction of the run, 1 if none did
+    TimeToFirstDelivery,
+    // Distance walked per ant
+    EnergySpent,
+    // Pheromone laid down per ant
+    PheromoneDeposited,
+}
+
+
+fn per_ant(total: f64, simulation_result: &SimulationResult) -> f64 {
+    total / usize::max(simulation_result.num_ants, 1) as f64
+}
+
+
+impl Reward for RewardTerm {
+    fn reward(&self, simulation_result: &SimulationResult) -> f64 {
+        let num_iters = usize::max(simulation_result.num_iters, 1) as f64;
+        match self {
+            RewardTerm::FoodReturned => simulation_result.food_returned_to_nest,
+            RewardTerm::DeliveryRate => simulation_result.food_returned_to_nest / num_iters,
+            RewardTerm::FoodRemaining => simulation_result.food_remaining,
+            RewardTerm::ProportionExplored => simulation_result.proportion_explored,
+            RewardTerm::TimeToFirstDelivery => match simulation_result.first_delivery_tick {
+                Some(tick) => f64::min(tick as f64 / num_iters, 1.0),
+                None => 1.0,
+            },
+            RewardTerm::EnergySpent => per_ant(simulation_result.distance_travelled, simulation_result),
+            RewardTerm::PheromoneDeposited => per_ant(simulation_result.pheromone_deposited, simulation_result),
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WeightedTerm {
+    pub term: RewardTerm,
+    pub weight: f64,
+}
+
+
+impl WeightedTerm {
+    pub fn new(term: RewardTerm, weight: f64) -> WeightedTerm {
+        WeightedTerm { term, weight }
+    }
+}
+
+
+impl Reward for WeightedTerm {
+    fn reward(&self, simulation_result: &SimulationResult) -> f64 {
+        self.weight * self.term.reward(simulation_result)
+    }
+}
+
+
+impl<R: Reward> Reward for [R] {
+    fn reward(&self, simulation_result: &SimulationResult) -> f64 {
+        self.iter().map(|term| term.reward(simulation_result)).sum()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_reward_terms() {
+        let mut simulation_result = SimulationResult::new(200, 3.0, 5.0, 0.25);
+        simulation_result.num_ants = 10;
+        simulation_result.first_delivery_tick = Some(50);
+        simulation_result.distance_travelled = 400.0;
+        simulation_result.pheromone_deposited = 2.0;
+
+        assert_eq!(RewardTerm::DeliveryRate.reward(&simulation_result), 0.015);
+        assert_eq!(RewardTerm::TimeToFirstDelivery.reward(&simulation_result), 0.25);
+        assert_eq!(RewardTerm::EnergySpent.reward(&simulation_result), 40.0);
+        assert_eq!(RewardTerm::PheromoneDeposited.reward(&simulation_result), 0.2);
+
+        let terms = [
+            WeightedTerm::new(RewardTerm::FoodReturned, 1.0),
+            WeightedTerm::new(RewardTerm::FoodRemaining, -0.1),
+            WeightedTerm::new(RewardTerm::TimeToFirstDelivery, -2.0),
+        ];
+        assert_eq!(terms.reward(&simulation_result), 3.0 - 0.5 - 0.5);
+
+        simulation_result.first_delivery_tick = None;
+        assert_eq!(RewardTerm::TimeToFirstDelivery.reward(&simulation_result), 1.0);
+        let custom = |result: &SimulationResult| result.food_returned_to_nest * 2.0;
+        assert_eq!(custom.reward(&simulation_result), 6.0);
+    }
+}
@@ -5,62 +5,340 @@ extern crate opengl_graphics;
 // extern crate blas_src;
 extern crate openblas_src;
 
+mod tui;
 
 use piston::window::WindowSettings;
 use piston::event_loop::{Events, EventLoop, EventSettings};
 use piston::input::RenderEvent;
 use glutin_window::GlutinWindow;
 use opengl_graphics::{OpenGL, GlGraphics};
+use ndarray::{Array, Dim};
 use ndarray_npy::read_npy;
+use std::sync::{Arc, Mutex};
 
 extern crate lib;
 
-use lib::simulation::colony::Colony;
-use lib::simulation::environment::Environment;
+use lib::config::SimulationConfig;
+use lib::simulation::controller::{ReplayController, SimulationController};
+use lib::simulation::editor::WorldEditor;
+use lib::simulation::replay::{EpisodeRecorder, Replay, ReplayPlayer};
+use lib::simulation::terminal_view::TerminalView;
 use lib::simulation::world_view::{WorldView, WorldViewSettings};
-use lib::neural_network::mlp::MLP;
+use lib::neural_network::mlp::{legacy_activations, MLP};
+use lib::neural_network::utils::Activation;
 
 
-const NUM_ANTS: usize = 50;
-const ARENA_SIZE: usize = 100;
-const DIFFUSION_RATE: f64 = 0.999;
 const PIXEL_SIZE: usize = 4;
-const RESOLUTION: usize = PIXEL_SIZE * ARENA_SIZE;
+const UPS: u64 = 100;
+const KEYFRAME_INTERVAL: usize = 100;
 
 
-fn main() {
-    let opengl = OpenGL::V3_2;
-    let settings = WindowSettings::new("Ant Colony Simulation", [RESOLUTION as f64; 2])
-        .graphics_api(opengl)
+const USAGE: &str = "Usage: visualize_simulation [--config path] [--model model.mlp | --weights model.npy --hidden 16,2 [--activations relu,tanh | --legacy-activations]] [--save-map path] [--tui] [--record episode.replay | --replay episode.replay]";
+
+
+struct Args {
+    config: SimulationConfig,
+    model_path: Option<String>,
+    weights_path: Option<String>,
+    hidden_sizes: Option<Vec<usize>>,
+    activations: Option<Vec<Activation>>,
+    // Linear hidden layers and a ReLU output, for .npy weights saved before activations could be chosen
+    legacy_activations: bool,
+    save_map_path: String,
+    // Draw in the terminal instead of opening a window
+    tui: bool,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+}
+
+
+fn exit_with_error(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+
+fn parse_args() -> Args {
+    let args: Vec<String> = std::env::args().collect();
+    let mut parsed_args = Args {
+        config: SimulationConfig::new(),
+        model_path: None,
+        weights_path: None,
+        hidden_sizes: None,
+        activations: None,
+        legacy_activations: false,
+        save_map_path: "edited_map.txt".to_string(),
+        tui: false,
+        record_path: None,
+        replay_path: None,
+    };
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--config" if i + 1 < args.len() => {
+                parsed_args.config = SimulationConfig::from_file(&args[i + 1]).unwrap_or_else(|e| {
+                    exit_with_error(format!("{}: {}", args[i + 1], e))
+                });
+                i += 2;
+            },
+            "--model" if i + 1 < args.len() => {
+                parsed_args.model_path = Some(args[i + 1].clone());
+                i += 2;
+            },
+            "--weights" if i + 1 < args.len() => {
+                parsed_args.weights_path = Some(args[i + 1].clone());
+                i += 2;
+            },
+            "--hidden" if i + 1 < args.len() => {
+                let hidden_sizes: Result<Vec<usize>, _> = args[i + 1].split(',').map(|size| size.trim().parse()).collect();
+                match hidden_sizes {
+                    Ok(hidden_sizes) if !hidden_sizes.is_empty() && !hidden_sizes.contains(&0) => {
+                        parsed_args.hidden_sizes = Some(hidden_sizes)
+                    },
+                    _ => exit_with_error(format!(
+                        "--hidden expects a comma separated list of layer sizes like 16,2, got {:?}", args[i + 1]
+                    )),
+                }
+                i += 2;
+            },
+            "--activations" if i + 1 < args.len() => {
+                let activations: Result<Vec<Activation>, _> = args[i + 1].split(',').map(|name| name.parse()).collect();
+                parsed_args.activations = Some(activations.unwrap_or_else(|e| {
+                    exit_with_error(format!("--activations expects a comma separated list like relu,tanh: {}", e))
+                }));
+                i += 2;
+            },
+            "--legacy-activations" => {
+                parsed_args.legacy_activations = true;
+                i += 1;
+            },
+            "--save-map" if i + 1 < args.len() => {
+                parsed_args.save_map_path = args[i + 1].clone();
+                i += 2;
+            },
+            "--record" if i + 1 < args.len() => {
+                parsed_args.record_path = Some(args[i + 1].clone());
+                i += 2;
+            },
+            "--replay" if i + 1 < args.len() => {
+                parsed_args.replay_path = Some(args[i + 1].clone());
+                i += 2;
+            },
+            "--tui" => {
+                parsed_args.tui = true;
+                i += 1;
+            },
+            arg => exit_with_error(format!("Unrecognised argument {:?}. {}", arg, USAGE)),
+        }
+    }
+    if parsed_args.model_path.is_some()
+        && (parsed_args.weights_path.is_some() || parsed_args.hidden_sizes.is_some() || parsed_args.activations.is_some()
+            || parsed_args.legacy_activations) {
+        exit_with_error(format!(
+            "--model files describe their own architecture, so --weights, --hidden and --activations can't be used with it. {}",
+            USAGE
+        ));
+    }
+    if parsed_args.legacy_activations && parsed_args.activations.is_some() {
+        exit_with_error(format!("--legacy-activations can't be used with --activations. {}", USAGE));
+    }
+    if parsed_args.replay_path.is_some() && (parsed_args.record_path.is_some() || parsed_args.tui) {
+        exit_with_error(format!("--replay can't be used with --record or --tui. {}", USAGE));
+    }
+    if let Some(hidden_sizes) = &parsed_args.hidden_sizes {
+        parsed_args.config.network.hidden_sizes = hidden_sizes.clone();
+    }
+    if let Some(activations) = &parsed_args.activations {
+        parsed_args.config.network.activations = Some(activations.clone());
+    }
+    if parsed_args.legacy_activations {
+        parsed_args.config.network.activations = Some(legacy_activations(parsed_args.config.network.hidden_sizes.len()));
+    }
+    if let Err(e) = parsed_args.config.validate() {
+        exit_with_error(e.to_string());
+    }
+
+    parsed_args
+}
+
+
+fn load_model(config: &mut SimulationConfig, model_path: &str) -> MLP {
+    let (decision_network, metadata) = MLP::load(model_path).unwrap_or_else(|e| {
+        exit_with_error(format!("{}: {}", model_path, e))
+    });
+    if let Err(e) = config.check_network(&decision_network, &metadata) {
+        exit_with_error(format!("{}: {}", model_path, e));
+    }
+    config.network.hidden_sizes = decision_network.layer_sizes();
+    config.network.activations = Some(decision_network.activations());
+
+    decision_network
+}
+
+
+fn load_decision_network(config: &SimulationConfig, weights_path: &Option<String>) -> MLP {
+    let path = match weights_path {
+        Some(path) => path,
+        None => return config.build_network(config.seed),
+    };
+    let weights: Array<f32, Dim<[usize; 1]>> = read_npy(path).unwrap_or_else(|e| {
+        exit_with_error(format!("Could not read weights from {}: {}", path, e))
+    });
+    let input_dimension = config.input_dimension();
+    let hidden_sizes = config.network.hidden_sizes.clone();
+    let num_parameters = MLP::num_parameters(input_dimension, &hidden_sizes);
+    if weights.len() != num_parameters {
+        exit_with_error(format!(
+            "{} has {} weights, but a network with {} inputs and hidden sizes {:?} needs {}. \
+             Pass the architecture the model was trained with using --hidden and --activations or --config.",
+            path, weights.len(), input_dimension, hidden_sizes, num_parameters
+        ));
+    }
+
+    MLP::from_flattened_weights(input_dimension, hidden_sizes, config.network.activations(), weights)
+}
+
+
+fn create_window(resolution: usize) -> GlutinWindow {
+    let settings = WindowSettings::new("Ant Colony Simulation", [resolution as f64; 2])
+        .graphics_api(OpenGL::V3_2)
         .exit_on_esc(true);
 
-    let mut window: GlutinWindow = settings.build()
-        .expect("Could not create window");
+    settings.build().expect("Could not create window")
+}
+
+
+// Plays a recorded episode. The editor isn't available since changing the world would make the
+// rest of the replay meaningless.
+fn play_replay(replay_path: &str) {
+    let replay = Replay::load(replay_path).unwrap_or_else(|e| exit_with_error(format!("{}: {}", replay_path, e)));
+    println!(
+        "Replaying ticks {} to {} of an episode with seed {}",
+        replay.start_tick(), replay.end_tick(), replay.header().seed
+    );
+    let mut controller = ReplayController::new(ReplayPlayer::new(replay), UPS);
+    let resolution = PIXEL_SIZE * controller.player.simulation().environment.size;
+    let mut window = create_window(resolution);
+    let mut events = Events::new(EventSettings::new().max_fps(100).ups(UPS));
+    let mut gl = GlGraphics::new(OpenGL::V3_2);
+    let mut world_view = WorldView::new(WorldViewSettings::new());
+    println!("Space: pause/resume, Left/Right: step back/forward while paused, Up/Down: change speed");
+    println!("[ / ]: jump back/forward a keyframe, R: restart, 1-5: toggle layers, Esc: quit");
+
+    let mut ups = UPS;
+    while let Some(e) = events.next(&mut window) {
+        if let Some(message) = controller.event(&e) {
+            println!("{}", message);
+        }
+        world_view.event(&e);
+        if controller.ups != ups {
+            ups = controller.ups;
+            events.set_ups(ups);
+        }
+
+        if let Some(args) = e.render_args() {
+            let simulation = controller.player.simulation();
+            gl.draw(args.viewport(), |c, g| {
+                graphics::clear([0.0; 4], g);
+                world_view.draw(&simulation.environment, &simulation.colony, &c, g);
+            });
+        }
+    }
+}
+
+
+fn save_recording(recorder: &Option<Arc<Mutex<EpisodeRecorder>>>, record_path: &Option<String>) {
+    if let (Some(recorder), Some(record_path)) = (recorder, record_path) {
+        let recorder = recorder.lock().unwrap();
+        if let Err(e) = recorder.replay().save(record_path) {
+            exit_with_error(format!("{}: {}", record_path, e));
+        }
+        println!("Recorded {} ticks to {}", recorder.replay().header().num_ticks, record_path);
+    }
+}
+
+
+fn main() {
+    let args = parse_args();
+    if let Some(replay_path) = &args.replay_path {
+        play_replay(replay_path);
+        return
+    }
+    let mut config = args.config;
+    let decision_network: MLP = match &args.model_path {
+        Some(model_path) => load_model(&mut config, model_path),
+        None => load_decision_network(&config, &args.weights_path),
+    };
+    let mut controller = SimulationController::new(config.clone(), decision_network, UPS).unwrap_or_else(|e| {
+        exit_with_error(e.to_string())
+    });
+    // Only the episode that is running now is recorded, resetting starts a new one without it
+    let recorder = args.record_path.as_ref().map(|_| {
+        let recorder = EpisodeRecorder::new(&controller.simulation, Some(config), controller.seed, KEYFRAME_INTERVAL);
+        let recorder = Arc::new(Mutex::new(recorder));
+        controller.simulation.add_observer(Box::new(recorder.clone()));
+        recorder
+    });
+    if args.tui {
+        let mut view = TerminalView::new(WorldViewSettings::new());
+        if let Err(e) = tui::run(&mut controller, &mut view) {
+            exit_with_error(format!("Terminal error: {}", e));
+        }
+        save_recording(&recorder, &args.record_path);
+        return
+    }
+    let resolution = PIXEL_SIZE * controller.simulation.environment.size;
+
+    let opengl = OpenGL::V3_2;
+    let mut window = create_window(resolution);
     let event_settings: EventSettings = EventSettings::new()
         .max_fps(100)
-        .ups(100);
+        .ups(UPS);
 
     let mut events = Events::new(event_settings);
     let mut gl = GlGraphics::new(opengl);
 
-    //let weights = read_npy("/home/reeldata/Documents/ant_sim/src/visualize_simulation/trial_3.npy").unwrap();
-
-    let decision_network: MLP = MLP::new(38, vec![16, 1]);
-    let mut colony = Colony::new(NUM_ANTS, decision_network);
-    let mut environment = Environment::new(ARENA_SIZE, DIFFUSION_RATE);
-    let world_view = WorldView::new(WorldViewSettings::new());
+    let mut world_view = WorldView::new(WorldViewSettings::new());
+    // Recordings only hold the ants' directions between keyframes, so edits would be missing from
+    // the replay and it would drift from what was shown. Editing is turned off while recording.
+    let mut editor = match recorder {
+        Some(_) => None,
+        None => Some(WorldEditor::new(PIXEL_SIZE, args.save_map_path)),
+    };
+    println!("Space: pause/resume, Right: single step while paused, Up/Down: change speed, R: reset with a new seed");
+    println!("1-5: toggle nest pheromone, food pheromone, food, ants and visited cells, Esc: quit");
+    match &editor {
+        Some(editor) => {
+            println!("Mouse: left click paints and right click erases with the edit tool. W: walls, F: food, E: erase pheromone, N: move nest");
+            println!("[ / ]: change the food amount, S: save the map to {}", editor.save_path);
+        },
+        None => println!("Editing the world is turned off while recording"),
+    }
 
+    let mut ups = UPS;
     while let Some(e) = events.next(&mut window) {
-        environment.update_piston(&e);
-        colony.update_piston(&mut environment, &e);
+        if let Some(message) = controller.event(&e) {
+            println!("{}", message);
+        }
+        world_view.event(&e);
+        if let Some(editor) = &mut editor {
+            if let Some(message) = editor.event(&e, &mut controller.simulation.environment) {
+                println!("{}", message);
+            }
+        }
+        if controller.ups != ups {
+            ups = controller.ups;
+            events.set_ups(ups);
+        }
         
         if let Some(args) = e.render_args() {
             gl.draw(args.viewport(), |c, g| {
                 use graphics::{clear};
 
                 clear([0.0; 4], g);
-                world_view.draw(&environment, &c, g);
+                world_view.draw(&controller.simulation.environment, &controller.simulation.colony, &c, g);
             });
         }
     }
+    save_recording(&recorder, &args.record_path);
 }
\ No newline at end of file
@@ -0,0 +1,123 @@
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+use piston::input::Key;
+
+use lib::simulation::controller::SimulationController;
+use lib::simulation::terminal_view::{status_line, TerminalView};
+
+
+// Redrawing is much slower than stepping, so the screen is only refreshed about 30 times a second
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+const HELP: &str = "Space: pause/resume  Right: step  Up/Down: speed  R: reset  1-5: layers  Q: quit";
+
+
+// Takes over the terminal until Q, Esc or Ctrl-C is pressed, and puts it back the way it was
+// even if drawing fails
+pub fn run(controller: &mut SimulationController, view: &mut TerminalView) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(stdout, terminal::EnterAlternateScreen, terminal::DisableLineWrap, cursor::Hide)?;
+    let result = event_loop(controller, view, &mut stdout);
+    execute!(stdout, cursor::Show, terminal::EnableLineWrap, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+
+    result
+}
+
+
+// The same keys as the window, so both can share SimulationController and WorldView
+fn to_key(code: KeyCode) -> Option<Key> {
+    match code {
+        KeyCode::Char(' ') => Some(Key::Space),
+        KeyCode::Right => Some(Key::Right),
+        KeyCode::Up => Some(Key::Up),
+        KeyCode::Down => Some(Key::Down),
+        KeyCode::Char('r') | KeyCode::Char('R') => Some(Key::R),
+        KeyCode::Char('1') => Some(Key::D1),
+        KeyCode::Char('2') => Some(Key::D2),
+        KeyCode::Char('3') => Some(Key::D3),
+        KeyCode::Char('4') => Some(Key::D4),
+        KeyCode::Char('5') => Some(Key::D5),
+        _ => None,
+    }
+}
+
+
+fn is_quit(key_event: &KeyEvent) -> bool {
+    match key_event.code {
+        KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Esc => true,
+        // Raw mode stops Ctrl-C from sending SIGINT
+        KeyCode::Char('c') => key_event.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+
+fn event_loop(controller: &mut SimulationController, view: &mut TerminalView, stdout: &mut Stdout) -> io::Result<()> {
+    let mut message = String::new();
+    let mut next_update = Instant::now();
+    let mut next_frame = Instant::now();
+    loop {
+        if Instant::now() >= next_frame {
+            draw(stdout, controller, view, &message)?;
+            next_frame = Instant::now() + FRAME_INTERVAL;
+        }
+
+        let deadline = if controller.paused { next_frame } else { next_frame.min(next_update) };
+        if event::poll(deadline.saturating_duration_since(Instant::now()))? {
+            match event::read()? {
+                Event::Key(key_event) if key_event.kind != KeyEventKind::Release => {
+                    if is_quit(&key_event) {
+                        return Ok(())
+                    }
+                    if let Some(key) = to_key(key_event.code) {
+                        if let Some(new_message) = controller.key_pressed(key) {
+                            message = new_message;
+                        }
+                        view.key_pressed(key);
+                        next_frame = Instant::now();
+                    }
+                },
+                Event::Resize(_, _) => {
+                    execute!(stdout, terminal::Clear(ClearType::All))?;
+                    next_frame = Instant::now();
+                },
+                _ => {},
+            }
+            continue
+        }
+
+        let now = Instant::now();
+        if !controller.paused && now >= next_update {
+            controller.simulation.step();
+            next_update += Duration::from_secs_f64(1.0 / controller.ups as f64);
+            // Run as fast as possible when stepping can't keep up, rather than catching up later
+            if next_update < now {
+                next_update = now;
+            }
+        }
+    }
+}
+
+
+fn draw(stdout: &mut Stdout, controller: &SimulationController, view: &TerminalView, message: &str) -> io::Result<()> {
+    let simulation = &controller.simulation;
+    // Leave room for the status and help lines, the grid is cut off in small terminals
+    let (_, rows) = terminal::size()?;
+    let max_lines = (rows as usize).saturating_sub(2);
+
+    queue!(stdout, cursor::MoveTo(0, 0))?;
+    for line in view.render(&simulation.environment, &simulation.colony).iter().take(max_lines) {
+        write!(stdout, "{}\r\n", line)?;
+    }
+    write!(stdout, "{}  {}", status_line(simulation, controller.paused, controller.ups), message)?;
+    queue!(stdout, terminal::Clear(ClearType::UntilNewLine))?;
+    write!(stdout, "\r\n{}", HELP)?;
+    queue!(stdout, terminal::Clear(ClearType::UntilNewLine))?;
+
+    stdout.flush()
+}